@@ -1,55 +1,989 @@
 pub mod compile_html;
 
-use crate::models::grid::Grid;
-use crate::models::grid_bag::GridBag;
+use crate::models::aspect_ratio::AspectRatio;
+use crate::models::grid::{FillConfig, Grid, InsertionConfig};
+use crate::models::grid_bag::{GridBag, GridWeight};
+use crate::models::io::{GridOutput, GRID_OUTPUT_VERSION};
+use crate::models::letter::Letter;
 use crate::models::merge_dag::MergeDag;
-use crate::models::token::Token;
+use crate::models::phrase::Phrase;
+use crate::models::positioned_token::{Direction, XY};
+use crate::models::text::Text;
+use crate::models::token::{Token, TokenId};
 use crate::models::token_relations::TokenRelations;
 use crate::models::word::WordId;
-use crate::{AspectRatio, Phrase};
+use anyhow::{anyhow, bail, ensure, Error, Result};
 use itertools::Itertools;
+use ordered_float::OrderedFloat;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
 use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::str::FromStr;
+
+/// How many times the maximum grid dimension can be relaxed when no grid can accept a token
+const MAX_RELAXATIONS: i32 = 10;
+
+/// Configure how the grid is built
+#[derive(Debug, Clone, Copy)]
+pub struct BuildGridConfig {
+    /// How many grids at most are kept at each step
+    pub max_grid_bag_size: usize,
+    pub insertion: InsertionConfig,
+    pub aspect_ratio: AspectRatio,
+    /// The maximum width and height of the grid. This is relaxed when no grid can accept a token.
+    pub max_grid_dimension: Option<i16>,
+    /// How much to favor grids in which the tokens that appear together in phrases are close
+    pub proximity_weight: f64,
+    /// How much to penalize grids in which consecutive tokens of a phrase change direction
+    pub direction_change_penalty: f64,
+    pub grid_weight: GridWeight,
+    pub insert_order: InsertOrder,
+    /// The maximum number of tokens to build a grid from. The memory used to build it grows with
+    /// the square of this number.
+    pub max_tokens: Option<usize>,
+    /// How many threads build the grid. When absent, all cores are used.
+    pub threads: Option<usize>,
+    /// The seed of the random choices. When absent, a random one is used.
+    pub random_seed: Option<u64>,
+    /// Show a progress bar instead of logging each insertion. Requires the "progress" feature.
+    pub progress: bool,
+}
+
+/// Determine in which order the tokens are inserted into the grids
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InsertOrder {
+    /// Start from the "outer" tokens, that is, the tokens with the least depth in the token graph
+    Depth,
+    /// Start from the tokens that are the hardest to place, while the grids are still flexible.
+    /// See [`difficulty()`].
+    Hardest,
+}
+
+/// The best grid that was built, with the conditions worth the attention of the user
+#[derive(Debug, Clone)]
+pub struct BuildReport {
+    pub grid: Grid,
+    pub warnings: Vec<Warning>,
+}
+
+/// A condition that does not prevent building a grid, but may make it worse than expected
+#[derive(Debug, Clone, PartialEq)]
+pub enum Warning {
+    /// The word is longer than the maximum grid dimension, which will be relaxed
+    LongWord { word: Text, required: usize },
+    /// No grid could accept the token, so the maximum grid dimension was relaxed
+    RelaxedDimension { token: Text, dimension: i16 },
+    /// The phrases form this many groups that share no word, which are placed without any
+    /// relation to each other
+    Islands { count: usize },
+    /// The word can be read from `start`, relative to the top-left corner, where it was not placed
+    ConfusableRead {
+        word: Text,
+        start: XY,
+        direction: Direction,
+    },
+    /// Honoring the aspect ratio would add more than `max_padding` of the cells, so the grid keeps
+    /// its natural size
+    AspectNotHonored {
+        max_padding: f64,
+        width: i16,
+        height: i16,
+    },
+}
+
+impl FromStr for InsertOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "depth" => Ok(InsertOrder::Depth),
+            "hardest" => Ok(InsertOrder::Hardest),
+            _ => Err(anyhow!("Insert order was not recognized: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Warning::LongWord { word, required } => write!(
+                f,
+                "The word {} requires a grid dimension of at least {}, so the maximum will be \
+                relaxed",
+                word, required
+            ),
+            Warning::RelaxedDimension { token, dimension } => write!(
+                f,
+                "No grid can accept {}: relaxed maximum grid dimension to {}",
+                token, dimension
+            ),
+            Warning::Islands { count } => write!(
+                f,
+                "The phrases form {} groups that share no word, so they are placed independently",
+                count
+            ),
+            Warning::ConfusableRead {
+                word,
+                start,
+                direction,
+            } => write!(
+                f,
+                "{} can be read {:?} from ({}, {}), where it was not placed",
+                word, direction, start.x, start.y
+            ),
+            Warning::AspectNotHonored {
+                max_padding,
+                width,
+                height,
+            } => write!(
+                f,
+                "Honoring the aspect ratio would add more than {} of the cells, so the grid keeps \
+                its natural size of {}x{}",
+                max_padding, width, height
+            ),
+        }
+    }
+}
+
+/// Estimate how hard it is to place a token: long tokens, tokens with many ordering constraints and
+/// tokens with rare letters have fewer valid positions
+fn difficulty(token: &Token, relations: &TokenRelations) -> f64 {
+    let max_frequency = Letter::E.frequency();
+    let rarity: f64 = token
+        .text()
+        .letters()
+        .iter()
+        .map(|letter| (max_frequency / letter.frequency()).ln())
+        .sum();
+
+    token.letters_len() as f64 + relations.num_constraints(token.id) as f64 + rarity
+}
+
+/// Check that any grid formed by tokens with the given lengths fits in the `i16` coordinates. In
+/// the worst case, all tokens are placed one after the other, separated by some spaces.
+pub fn check_coordinate_range(letters_lens: impl Iterator<Item = usize>) -> Result<()> {
+    const MAX_SPACING: usize = 3;
+    let max_span: usize = letters_lens.map(|len| len + MAX_SPACING).sum();
+
+    ensure!(
+        max_span <= i16::MAX as usize,
+        "The grid could span up to {} cells, which is more than the maximum {}",
+        max_span,
+        i16::MAX
+    );
+
+    Ok(())
+}
+
+/// Check that the longest token can fit in the maximum grid dimension, at least after relaxing it.
+/// Otherwise, the build would only fail after inserting all the shorter tokens.
+fn check_token_lengths<'a>(
+    tokens: impl Iterator<Item = &'a Token>,
+    max_grid_dimension: i16,
+) -> Result<Option<Warning>> {
+    let longest = match tokens.max_by_key(|token| token.letters_len()) {
+        None => return Ok(None),
+        Some(longest) => longest,
+    };
+
+    let required = longest.letters_len();
+    let relaxed_dimension = max_grid_dimension as usize + MAX_RELAXATIONS as usize;
+    ensure!(
+        required <= relaxed_dimension,
+        "The word {} requires a grid dimension of at least {}, but the maximum is {}, even after \
+        relaxing it",
+        longest.text(),
+        required,
+        relaxed_dimension
+    );
+    if required > max_grid_dimension as usize {
+        return Ok(Some(Warning::LongWord {
+            word: longest.text().clone(),
+            required,
+        }));
+    }
+
+    Ok(None)
+}
+
+/// Count the groups of tokens that never appear in the same phrase as the tokens of another group
+fn count_islands(token_graph: &MergeDag<WordId, Token>, relations: &TokenRelations) -> usize {
+    let mut neighbors: BTreeMap<TokenId, Vec<TokenId>> = BTreeMap::new();
+    for &(a, b, _) in relations.co_occurrences() {
+        neighbors.entry(a).or_default().push(b);
+        neighbors.entry(b).or_default().push(a);
+    }
+
+    let mut visited = BTreeSet::new();
+    let mut islands = 0;
+    for (_, token) in token_graph.groups() {
+        if !visited.insert(token.id) {
+            continue;
+        }
+        islands += 1;
+
+        let mut pending = vec![token.id];
+        while let Some(token_id) = pending.pop() {
+            for &neighbor in neighbors.get(&token_id).into_iter().flatten() {
+                if visited.insert(neighbor) {
+                    pending.push(neighbor);
+                }
+            }
+        }
+    }
+
+    islands
+}
+
+/// Return the warnings about the grid itself: the words of the tokens that can be read where they
+/// were not placed
+pub fn grid_warnings(grid: &Grid, token_graph: &MergeDag<WordId, Token>) -> Vec<Warning> {
+    let real_words = token_graph
+        .groups()
+        .map(|(_, token)| token.text().clone())
+        .collect_vec();
+
+    grid.confusable_reads(&real_words)
+        .into_iter()
+        .map(|(start, direction, word)| Warning::ConfusableRead {
+            word,
+            start: start - grid.top_left(),
+            direction,
+        })
+        .collect()
+}
+
+/// Re-roll the random letters of a grid, keeping its minimal grid and its phrase positions
+pub fn refill(grid: &GridOutput, fill: FillConfig, random: &mut impl Rng) -> Result<GridOutput> {
+    let height = grid.grid.len() as i16;
+    let width = grid.grid.first().map(|row| row.len()).unwrap_or(0) as i16;
+
+    let mut new_grid = Grid::from_letters(&grid.minimal_grid);
+    new_grid.fill_to_size(width, height, fill, random)?;
+    let mut letters = new_grid
+        .to_letters()
+        .into_iter()
+        .map(|row| {
+            row.into_iter()
+                .map(|letter| letter.expect("Grid is totally filled"))
+                .collect_vec()
+        })
+        .collect_vec();
+    for &(x, y) in &grid.fixed_cells {
+        let fixed = grid
+            .grid
+            .get(y as usize)
+            .and_then(|row| row.get(x as usize))
+            .ok_or_else(|| anyhow!("Fixed cell ({}, {}) is outside the grid", x, y))?;
+        letters[y as usize][x as usize] = *fixed;
+    }
+
+    // The minimal grid is centered the same way as when the grid was built, so the phrases keep
+    // their positions
+    for word in grid.phrases.iter().flat_map(|phrase| &phrase.words) {
+        ensure!(
+            word.read(&grid.grid)? == word.text,
+            "The word {} is not at its letter positions. Only (x, y) coordinates can be refilled, \
+            not the ones written with `--coord-order rowcol`",
+            word.text
+        );
+        ensure!(
+            word.read(&letters)? == word.text,
+            "The minimal grid is not at the expected position in the grid"
+        );
+    }
+
+    Ok(GridOutput {
+        version: GRID_OUTPUT_VERSION,
+        minimal_grid: grid.minimal_grid.clone(),
+        grid: letters,
+        fixed_cells: grid.fixed_cells.clone(),
+        phrases: grid.phrases.clone(),
+    })
+}
 
 pub fn build_grid(
     phrases: &[Phrase],
     token_graph: &MergeDag<WordId, Token>,
-    max_grid_bag_size: usize,
-    allow_diagonal: bool,
-    aspect_ratio: AspectRatio,
-) -> Grid {
+    config: BuildGridConfig,
+) -> Result<BuildReport> {
+    build_grid_from(Grid::new(), phrases, token_graph, config)
+}
+
+/// Build a grid around the tokens that were already placed in `seed`. Only the remaining tokens are
+/// inserted, so the seeded tokens keep their positions.
+pub fn build_grid_from(
+    seed: Grid,
+    phrases: &[Phrase],
+    token_graph: &MergeDag<WordId, Token>,
+    config: BuildGridConfig,
+) -> Result<BuildReport> {
+    let (grid_bag, mut warnings) = build_grid_bag(seed, phrases, token_graph, config)?;
+    let grid = grid_bag.best_grid().clone();
+    warnings.extend(grid_warnings(&grid, token_graph));
+
+    Ok(BuildReport { grid, warnings })
+}
+
+/// Like [`build_grid_from()`], but return all the grids that were kept at the last step, with the
+/// warnings raised while building them. See [`grid_warnings()`] for the warnings about the chosen
+/// grid.
+pub fn build_grid_bag(
+    seed: Grid,
+    phrases: &[Phrase],
+    token_graph: &MergeDag<WordId, Token>,
+    config: BuildGridConfig,
+) -> Result<(GridBag, Vec<Warning>)> {
+    let mut warnings = vec![];
+    if let Some(max_grid_dimension) = config.max_grid_dimension {
+        warnings.extend(check_token_lengths(
+            token_graph.groups().map(|(_, token)| token),
+            max_grid_dimension,
+        )?);
+    }
+
+    let num_tokens = token_graph.groups_len();
+    log::info!("Will build grid with {} tokens", num_tokens);
+    if let Some(max_tokens) = config.max_tokens {
+        ensure!(
+            num_tokens <= max_tokens,
+            "There are {} tokens, more than the maximum {}. Try to merge more words, for example \
+            with a larger chain growth head space",
+            num_tokens,
+            max_tokens
+        );
+    }
+
     let relations = TokenRelations::new(token_graph, phrases);
+    let islands = count_islands(token_graph, &relations);
+    if islands > 1 {
+        warnings.push(Warning::Islands { count: islands });
+    }
 
-    // List in which order the tokens will be merged into the grid bags: start from the "outer"
-    // tokens, that is, the tokens with the least depth.
-    let tokens_to_insert = token_graph
+    // List in which order the tokens will be merged into the grid bags
+    let remaining_tokens = token_graph
         .group_depths()
         .into_iter()
-        .sorted_by_key(|&(token_id, depth)| {
-            let token = &token_graph[token_id];
-            (depth, Reverse(token.text.letters().len()), token.id)
-        })
-        .map(|(token_id, _)| &token_graph[token_id])
-        .collect_vec();
+        .filter(|&(token_id, _)| !seed.contains_token(token_graph[token_id].id))
+        .map(|(token_id, depth)| (&token_graph[token_id], depth));
+    let tokens_to_insert = match config.insert_order {
+        InsertOrder::Depth => remaining_tokens
+            .sorted_by_key(|&(token, depth)| (depth, Reverse(token.letters_len()), token.id))
+            .map(|(token, _)| token)
+            .collect_vec(),
+        InsertOrder::Hardest => remaining_tokens
+            .map(|(token, _)| token)
+            .sorted_by_key(|&token| {
+                (
+                    Reverse(OrderedFloat(difficulty(token, &relations))),
+                    token.id,
+                )
+            })
+            .collect_vec(),
+    };
     log::debug!(
         "Will build grid with tokens: {}",
         tokens_to_insert.iter().format(", ")
     );
 
     // Regroup tokens into grids
-    let mut grid_bag = GridBag::new(aspect_ratio);
+    let mut grid_bag = GridBag::new_from(
+        seed,
+        config.aspect_ratio,
+        config.proximity_weight,
+        config.direction_change_penalty,
+        config.grid_weight,
+        &relations,
+    );
+    let mut random = match config.random_seed {
+        Some(random_seed) => SmallRng::seed_from_u64(random_seed),
+        None => SmallRng::from_entropy(),
+    };
+    let mut max_grid_dimension = config.max_grid_dimension;
+    let mut relaxations = 0;
     let num_tokens = tokens_to_insert.len();
-    for (i, inserting_token) in tokens_to_insert.into_iter().enumerate() {
+    let progress = Progress::new(config.progress, num_tokens)?;
+    let insert_all = || {
+        for (i, inserting_token) in tokens_to_insert.into_iter().enumerate() {
+            progress.inserting(inserting_token, i, num_tokens, &grid_bag);
+
+            while !grid_bag.insert(
+                &relations,
+                inserting_token,
+                config.insertion,
+                max_grid_dimension,
+                &mut random,
+            ) {
+                match &mut max_grid_dimension {
+                    Some(dimension) if relaxations < MAX_RELAXATIONS => {
+                        *dimension += 1;
+                        relaxations += 1;
+                        warnings.push(Warning::RelaxedDimension {
+                            token: inserting_token.text().clone(),
+                            dimension: *dimension,
+                        });
+                    }
+                    _ => bail!("No grid can accept {}", inserting_token),
+                }
+            }
+            grid_bag.trim(config.max_grid_bag_size, &mut random);
+        }
+        progress.finish();
+
+        Ok((grid_bag, warnings))
+    };
+
+    match config.threads {
+        None => insert_all(),
+        Some(threads) => rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()?
+            .install(insert_all),
+    }
+}
+
+/// Report how the insertions progress, either as a progress bar updated in place or as one log line
+/// per insertion
+struct Progress {
+    #[cfg(feature = "progress")]
+    bar: Option<indicatif::ProgressBar>,
+}
+
+impl Progress {
+    #[cfg_attr(not(feature = "progress"), allow(unused_variables))]
+    fn new(enabled: bool, num_tokens: usize) -> Result<Self> {
+        ensure!(
+            !enabled || cfg!(feature = "progress"),
+            "The progress bar requires building with the \"progress\" feature"
+        );
+
+        #[cfg(feature = "progress")]
+        let bar = if enabled {
+            let bar = indicatif::ProgressBar::new(num_tokens as u64);
+            bar.set_style(
+                indicatif::ProgressStyle::default_bar()
+                    .template("{bar:40} {pos}/{len} tokens, {msg}")?,
+            );
+            Some(bar)
+        } else {
+            None
+        };
+
+        Ok(Progress {
+            #[cfg(feature = "progress")]
+            bar,
+        })
+    }
+
+    fn inserting(&self, token: &Token, i: usize, num_tokens: usize, grid_bag: &GridBag) {
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.bar {
+            let (width, height) = grid_bag.best_grid().size();
+            bar.set_position(i as u64);
+            bar.set_message(format!(
+                "{} grids, best is {}x{}",
+                grid_bag.grids().len(),
+                width,
+                height
+            ));
+            return;
+        }
+
         log::info!(
             "Insert {} into bag with {} grids ({}/{})",
-            inserting_token,
+            token,
             grid_bag.grids().len(),
             i,
             num_tokens
         );
+    }
+
+    fn finish(&self) {
+        #[cfg(feature = "progress")]
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_phrases::{generate_phrases_at, HourRollover, SpellOptions};
+    use crate::models::io::GridInput;
+    use crate::models::language::Language;
+    use crate::models::phrase_book::PhraseBook;
+    use crate::models::position_restriction::ReadingAxis;
+    use crate::models::positioned_token::XY;
+    use crate::models::positioned_token::{OrientedToken, PositionedToken};
+    use crate::models::time::Time;
+    use crate::tokenize::{tokenize, MergeObjective};
+    use rand::seq::SliceRandom;
 
-        grid_bag.insert(&relations, inserting_token, allow_diagonal);
-        grid_bag.trim(max_grid_bag_size);
+    fn test_config() -> BuildGridConfig {
+        BuildGridConfig {
+            max_grid_bag_size: 100,
+            insertion: InsertionConfig {
+                allow_diagonal: false,
+                reading_axis: ReadingAxis::RowMajor,
+                max_pivots_per_letter: None,
+                separate_phrases: false,
+                separate_shared_boundaries: false,
+            },
+            aspect_ratio: "16:9".parse().unwrap(),
+            max_grid_dimension: None,
+            proximity_weight: 0.,
+            direction_change_penalty: 0.,
+            grid_weight: GridWeight::Area,
+            insert_order: InsertOrder::Depth,
+            max_tokens: None,
+            threads: None,
+            random_seed: None,
+            progress: false,
+        }
     }
 
-    grid_bag.best_grid().clone()
+    #[test]
+    fn refill_keeps_minimal_grid() {
+        use crate::models::io::{CoordOrder, GridOutputPhrase, GridOutputWord};
+        use crate::models::letter::Letter;
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let minimal_grid = vec![vec![Some(Letter::H), Some(Letter::I)]];
+        let mut filled = Grid::from_letters(&minimal_grid);
+        let random = &mut SmallRng::seed_from_u64(17);
+        filled
+            .fill_to_size(4, 3, FillConfig::default(), random)
+            .unwrap();
+        let letters = filled
+            .to_letters()
+            .into_iter()
+            .map(|row| row.into_iter().map(Option::unwrap).collect_vec())
+            .collect_vec();
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid,
+            grid: letters,
+            fixed_cells: vec![(0, 0), (3, 2)],
+            phrases: vec![GridOutputPhrase {
+                words: vec![GridOutputWord {
+                    letters: vec![(1, 1), (2, 1)],
+                    text: "HI".to_owned(),
+                }],
+            }],
+        };
+
+        let refilled = refill(&grid, FillConfig::default(), random).unwrap();
+        assert_eq!(refilled.minimal_grid, grid.minimal_grid);
+        assert_eq!(&refilled.grid[1][1..3], &[Letter::H, Letter::I]);
+        assert_eq!(refilled.phrases[0].words[0].letters, vec![(1, 1), (2, 1)]);
+        assert_eq!(refilled.grid[0][0], grid.grid[0][0]);
+        assert_eq!(refilled.grid[2][3], grid.grid[2][3]);
+
+        // A word outside of the grid is an error, not a panic
+        let mut outside = grid.clone();
+        outside.phrases[0].words[0].letters = vec![(5, 1), (6, 1)];
+        assert!(refill(&outside, FillConfig::default(), random).is_err());
+
+        // So is a grid written with `--coord-order rowcol`
+        let row_col = grid.with_coord_order(CoordOrder::RowCol);
+        assert!(refill(&row_col, FillConfig::default(), random).is_err());
+    }
+
+    #[test]
+    fn test_language() {
+        let times = Time::range(Time::new(1, 0), Time::new(3, 0), 30).collect_vec();
+        let mut phrase_book = PhraseBook::default();
+        for phrase in generate_phrases_at(
+            Language::Test,
+            &times,
+            SpellOptions::new(HourRollover::TwentyFour),
+        ) {
+            phrase_book.insert_phrase(phrase.texts);
+        }
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+
+        let grid = build_grid(phrase_book.phrases(), &token_graph, test_config())
+            .unwrap()
+            .grid;
+
+        // The phrases from 1:00 to 2:30 share all their words: HOUR, ONE, TWO, MINUTE, THREE and
+        // ZERO
+        assert_eq!(token_graph.groups_len(), 6);
+        assert_eq!(grid.num_tokens(), 6);
+    }
+
+    #[test]
+    fn seeded_token_keeps_position() {
+        let phrase_book =
+            PhraseBook::from_phrases(&["IT IS ONE", "IT IS TWO", "IT IS HALF PAST ONE"]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+
+        let (_, half) = token_graph
+            .groups()
+            .find(|(_, token)| token.text().to_string() == "HALF")
+            .unwrap();
+        let oriented = OrientedToken::orientations(half, false)[0];
+        let mut seed = Grid::new();
+        seed.insert(half, PositionedToken::new(oriented, XY::new(5, 5)));
+
+        let grid = build_grid_from(seed, phrase_book.phrases(), &token_graph, test_config())
+            .unwrap()
+            .grid;
+
+        let positions = grid.positions_for_token(half.id).unwrap().collect_vec();
+        assert_eq!(
+            positions,
+            vec![XY::new(5, 5), XY::new(6, 5), XY::new(7, 5), XY::new(8, 5)]
+        );
+        for phrase in phrase_book.phrases() {
+            for &word in &phrase.words {
+                assert!(grid.contains_token(token_graph.group(word).1.id));
+            }
+        }
+    }
+
+    #[test]
+    fn relax_max_grid_dimension() {
+        let phrase_book = PhraseBook::from_phrases(&["IT IS QUARTER PAST ONE"]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+
+        // "QUARTER" alone does not fit
+        let config = BuildGridConfig {
+            max_grid_dimension: Some(5),
+            ..test_config()
+        };
+        let report = build_grid(phrase_book.phrases(), &token_graph, config).unwrap();
+        let relaxed = report
+            .warnings
+            .iter()
+            .filter_map(|warning| match warning {
+                Warning::RelaxedDimension { dimension, .. } => Some(*dimension),
+                _ => None,
+            })
+            .collect_vec();
+        assert!(!relaxed.is_empty());
+        assert_eq!(relaxed, (6..6 + relaxed.len() as i16).collect_vec());
+
+        let final_dimension = 5 + relaxed.len() as i16;
+        assert!(final_dimension >= 7);
+        let (width, height) = report.grid.size();
+        assert!(width.max(height) <= final_dimension);
+    }
+
+    #[test]
+    fn proximity_weight() {
+        let phrase_book = PhraseBook::from_phrases(&["ONE TWO", "SIX TEN", "ONE TEN"]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+        let distance = |proximity_weight| {
+            let config = BuildGridConfig {
+                proximity_weight,
+                ..test_config()
+            };
+            let grid = build_grid(phrase_book.phrases(), &token_graph, config)
+                .unwrap()
+                .grid;
+            grid.co_occurrence_distance(relations.co_occurrences())
+        };
+
+        assert!(distance(100.) < distance(0.));
+    }
+
+    #[test]
+    fn direction_change_penalty() {
+        let phrase_book = PhraseBook::from_phrases(&[
+            "IT IS HALF PAST ONE",
+            "IT IS QUARTER PAST SIX",
+            "IT IS SIX",
+        ]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+        let changes = |direction_change_penalty, random_seed| {
+            let config = BuildGridConfig {
+                direction_change_penalty,
+                random_seed: Some(random_seed),
+                ..test_config()
+            };
+            let grid = build_grid(phrase_book.phrases(), &token_graph, config)
+                .unwrap()
+                .grid;
+            grid.direction_changes(relations.successions())
+        };
+
+        for random_seed in 0..4 {
+            assert!(changes(100., random_seed) < changes(0., random_seed));
+        }
+    }
+
+    #[test]
+    fn insert_order_hardest() {
+        let phrase_book = PhraseBook::from_phrases(&[
+            "IT IS ONE",
+            "IT IS QUARTER PAST ONE",
+            "IT IS HALF PAST ONE",
+            "IT IS SIX",
+            "IT IS QUARTER PAST SIX",
+            "IT IS HALF PAST SIX",
+        ]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+        let token = |text: &str| {
+            token_graph
+                .groups()
+                .find(|(_, token)| token.text().to_string() == text)
+                .unwrap()
+                .1
+        };
+        assert!(difficulty(token("QUARTER"), &relations) > difficulty(token("HALF"), &relations));
+        assert!(difficulty(token("SIX"), &relations) > difficulty(token("ONE"), &relations));
+
+        let area = |insert_order| {
+            let config = BuildGridConfig {
+                insert_order,
+                ..test_config()
+            };
+            let grid = build_grid(phrase_book.phrases(), &token_graph, config)
+                .unwrap()
+                .grid;
+            let (width, height) = grid.size();
+            width * height
+        };
+        assert!(area(InsertOrder::Hardest) <= area(InsertOrder::Depth));
+    }
+
+    #[test]
+    fn islands() {
+        let islands = |phrases: &[&str]| {
+            let phrase_book = PhraseBook::from_phrases(phrases);
+            let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+            build_grid(phrase_book.phrases(), &token_graph, test_config())
+                .unwrap()
+                .warnings
+                .into_iter()
+                .filter(|warning| matches!(warning, Warning::Islands { .. }))
+                .collect_vec()
+        };
+
+        assert_eq!(islands(&["IT IS ONE", "IT IS TWO"]), vec![]);
+        assert_eq!(
+            islands(&["IT IS ONE", "HALF PAST TWO", "IT IS SIX"]),
+            vec![Warning::Islands { count: 2 }]
+        );
+    }
+
+    #[test]
+    fn max_tokens() {
+        let phrase_book = PhraseBook::from_phrases(&["IT IS ONE", "IT IS TWO"]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let config = |max_tokens| BuildGridConfig {
+            max_tokens: Some(max_tokens),
+            ..test_config()
+        };
+
+        assert!(build_grid(phrase_book.phrases(), &token_graph, config(4)).is_ok());
+        let error = build_grid(phrase_book.phrases(), &token_graph, config(3)).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("There are 4 tokens, more than the maximum 3"));
+    }
+
+    #[test]
+    fn single_thread() {
+        let phrase_book = PhraseBook::from_phrases(&[
+            "IT IS ONE",
+            "IT IS QUARTER PAST ONE",
+            "IT IS HALF PAST ONE",
+            "IT IS TWO",
+        ]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let build = |threads| {
+            let config = BuildGridConfig {
+                threads,
+                random_seed: Some(17),
+                ..test_config()
+            };
+            build_grid(phrase_book.phrases(), &token_graph, config)
+                .unwrap()
+                .grid
+                .to_string()
+        };
+
+        assert_eq!(build(Some(1)), build(None));
+    }
+
+    #[test]
+    fn canonical_hash() {
+        let phrase_book =
+            PhraseBook::from_phrases(&["IT IS ONE", "IT IS QUARTER PAST ONE", "IT IS TWO"]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let build = || {
+            let config = BuildGridConfig {
+                random_seed: Some(17),
+                ..test_config()
+            };
+            build_grid(phrase_book.phrases(), &token_graph, config)
+                .unwrap()
+                .grid
+        };
+
+        let grid = build();
+        assert_eq!(build(), grid);
+        assert_eq!(build().canonical_hash(), grid.canonical_hash());
+
+        let mut shifted = grid.clone();
+        shifted.shift(XY::new(3, -2));
+        assert_eq!(shifted, grid);
+        assert_eq!(shifted.canonical_hash(), grid.canonical_hash());
+        assert_ne!(Grid::new(), grid);
+    }
+
+    #[test]
+    fn deterministic_pivots() {
+        let phrase_book = PhraseBook::from_phrases(&[
+            "IT IS TEN TO ONE",
+            "IT IS TWENTY PAST SEVEN",
+            "IT IS ONE",
+            "IT IS SEVEN",
+        ]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let build = || {
+            let mut config = BuildGridConfig {
+                max_grid_bag_size: 10,
+                random_seed: Some(17),
+                ..test_config()
+            };
+            config.insertion.max_pivots_per_letter = Some(1);
+            build_grid(phrase_book.phrases(), &token_graph, config)
+                .unwrap()
+                .grid
+                .to_string()
+        };
+
+        // The sampled pivots must not depend on the iteration order of the letters
+        let grid = build();
+        for _ in 0..10 {
+            assert_eq!(build(), grid);
+        }
+    }
+
+    #[test]
+    fn content_seed() {
+        let build = |grid_input: GridInput| {
+            let mut phrase_book = PhraseBook::default();
+            for phrase in &grid_input.phrases {
+                phrase_book.insert_phrase(phrase.texts.clone());
+            }
+            let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+            let config = BuildGridConfig {
+                random_seed: Some(grid_input.content_seed()),
+                ..test_config()
+            };
+            build_grid(phrase_book.phrases(), &token_graph, config)
+                .unwrap()
+                .grid
+        };
+
+        assert_eq!(build(GridInput::example()), build(GridInput::example()));
+    }
+
+    #[test]
+    fn grid_bag_entries() {
+        let phrase_book = PhraseBook::from_phrases(&["IT IS ONE", "IT IS TWO"]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+
+        let (grid_bag, _) = build_grid_bag(
+            Grid::new(),
+            phrase_book.phrases(),
+            &token_graph,
+            test_config(),
+        )
+        .unwrap();
+        let entries = grid_bag.entries();
+        assert_eq!(entries.len(), grid_bag.grids().len());
+        assert_eq!(entries[0].minimal_grid, grid_bag.best_grid().to_letters());
+        for entry in &entries {
+            assert_eq!(entry.minimal_grid.len(), entry.height as usize);
+            assert!(entry.width * entry.height <= entry.aspect_area);
+        }
+        assert!(entries
+            .iter()
+            .tuple_windows()
+            .all(|(a, b)| (a.aspect_area, a.num_letters) <= (b.aspect_area, b.num_letters)));
+    }
+
+    #[test]
+    fn random_phrases() {
+        use crate::models::letter::Letter;
+        use crate::models::text::Text;
+
+        let random = &mut SmallRng::seed_from_u64(17);
+        for _ in 0..200 {
+            // Draw from a small vocabulary, so that words repeat across phrases
+            let vocabulary = (0..random.gen_range(1..8))
+                .map(|_| {
+                    let word: String = (0..random.gen_range(1..6))
+                        .map(|_| random.gen::<Letter>().as_char())
+                        .collect();
+                    word.parse::<Text>().unwrap()
+                })
+                .collect_vec();
+            let mut phrase_book = PhraseBook::default();
+            for _ in 0..random.gen_range(1..6) {
+                let num_words = random.gen_range(1..=vocabulary.len().min(4));
+                let phrase = vocabulary
+                    .choose_multiple(random, num_words)
+                    .cloned()
+                    .collect();
+                phrase_book.insert_phrase(phrase);
+            }
+
+            let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+            let config = BuildGridConfig {
+                max_grid_bag_size: 10,
+                random_seed: Some(random.gen()),
+                ..test_config()
+            };
+            let mut grid = build_grid(phrase_book.phrases(), &token_graph, config)
+                .unwrap()
+                .grid;
+            for phrase in phrase_book.phrases() {
+                for &word in &phrase.words {
+                    let token = token_graph.group(word).1;
+                    assert!(grid.positions_for_token(token.id).is_some());
+                }
+            }
+
+            let (width, height) = grid.size();
+            grid.fill_to_size(width + 1, height + 2, FillConfig::default(), random)
+                .unwrap();
+            assert!(grid.iter_cells().all(|(_, letter)| letter.is_some()));
+        }
+    }
+
+    #[test]
+    fn token_longer_than_max_dimension() {
+        let phrase_book = PhraseBook::from_phrases(&["IT IS SUPERCALIFRAGILISTIC"]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let config = BuildGridConfig {
+            max_grid_dimension: Some(3),
+            ..test_config()
+        };
+
+        let error = build_grid(phrase_book.phrases(), &token_graph, config).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The word SUPERCALIFRAGILISTIC requires a grid dimension of at least 20, but the \
+            maximum is 13, even after relaxing it"
+        );
+    }
+
+    #[test]
+    fn coordinate_range() {
+        assert!(check_coordinate_range(vec![7; 3276].into_iter()).is_ok());
+        assert!(check_coordinate_range(vec![7; 3277].into_iter()).is_err());
+    }
 }
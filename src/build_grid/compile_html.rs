@@ -1,23 +1,80 @@
-use crate::{GridInput, GridOutput};
+use crate::models::io::{GridInput, GridOutput};
+use crate::models::letter::Letter;
 use itertools::Itertools;
-use std::collections::BTreeSet;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
 
-pub fn compile_html(grid_input: &GridInput, grid: &GridOutput) -> String {
+/// Map the letters to what is displayed for them, for example to draw the random letters with a
+/// "noise" glyph. The glyphs are inserted as HTML. Letters without a glyph are displayed as
+/// themselves.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GlyphMap {
+    /// The glyphs of the letters of the phrases, that is, of the minimal grid
+    #[serde(default)]
+    pub real: BTreeMap<Letter, String>,
+    /// The glyphs of the random letters
+    #[serde(default)]
+    pub decoy: BTreeMap<Letter, String>,
+}
+
+impl GlyphMap {
+    pub fn glyph(&self, letter: Letter, is_real: bool) -> String {
+        let glyphs = if is_real { &self.real } else { &self.decoy };
+        match glyphs.get(&letter) {
+            Some(glyph) => glyph.clone(),
+            None => letter.to_string(),
+        }
+    }
+}
+
+pub fn compile_html(grid_input: &GridInput, grid: &GridOutput, glyphs: &GlyphMap) -> String {
     let page = include_str!("template.html");
-    let page = page.replacen("${GRID}", &compile_grid(grid), 1);
+    let page = page.replacen("${GRID}", &compile_grid(grid, glyphs), 1);
     page.replacen("${PHRASES}", &compile_phrases(grid_input, grid), 1)
 }
 
-pub fn compile_grid(grid: &GridOutput) -> String {
+/// Compile the cells of the grid. The letters of the phrases carry a `data-token` attribute with
+/// the ids of the tokens that cover them and a `data-phrase` attribute with the indexes of their
+/// phrases, both space-separated, so that scripts can highlight whole words. The tokens are not
+/// recorded in [`GridOutput`], so each distinct word placement is numbered as a token, in order of
+/// appearance.
+pub fn compile_grid(grid: &GridOutput, glyphs: &GlyphMap) -> String {
+    let mut token_ids = BTreeMap::new();
+    let mut tokens_by_cell: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+    let mut phrases_by_cell: BTreeMap<_, BTreeSet<_>> = BTreeMap::new();
+    for (phrase_index, phrase) in grid.phrases.iter().enumerate() {
+        for word in &phrase.words {
+            let next_id = token_ids.len();
+            let token_id = *token_ids.entry(&word.letters).or_insert(next_id);
+            for &cell in &word.letters {
+                tokens_by_cell.entry(cell).or_default().insert(token_id);
+                phrases_by_cell
+                    .entry(cell)
+                    .or_default()
+                    .insert(phrase_index);
+            }
+        }
+    }
+
     grid.grid
         .iter()
         .enumerate()
         .format_with("\n", |(j, letters), f| {
             f(&"<tr>")?;
-            for (i, letter) in letters.iter().enumerate() {
+            for (i, &letter) in letters.iter().enumerate() {
+                let cell = (i as i16, j as i16);
+                let glyph = glyphs.glyph(letter, tokens_by_cell.contains_key(&cell));
+                let data = match (tokens_by_cell.get(&cell), phrases_by_cell.get(&cell)) {
+                    (Some(tokens), Some(phrases)) => format!(
+                        " data-token=\"{}\" data-phrase=\"{}\"",
+                        tokens.iter().format(" "),
+                        phrases.iter().format(" ")
+                    ),
+                    _ => String::new(),
+                };
                 f(&format_args!(
-                    "<td class=\"letter-off\">{}<span class=\"letter-on letter-on-{}-{}\">{}</span></td>",
-                    letter, i, j, letter
+                    "<td class=\"letter-off\">{}<span class=\"letter-on letter-on-{}-{}\"{}>{}</span></td>",
+                    glyph, i, j, data, glyph
                 ))?;
             }
             f(&"</tr>")
@@ -54,3 +111,73 @@ fn compile_phrases(grid_input: &GridInput, grid_output: &GridOutput) -> String {
         })
         .to_string()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::io::{GridOutputPhrase, GridOutputWord, GRID_OUTPUT_VERSION};
+
+    #[test]
+    fn glyphs() {
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: vec![vec![Some(Letter::H), Some(Letter::I)]],
+            grid: vec![vec![Letter::H, Letter::I, Letter::H]],
+            fixed_cells: vec![],
+            phrases: vec![GridOutputPhrase {
+                words: vec![GridOutputWord {
+                    letters: vec![(0, 0), (1, 0)],
+                    text: "HI".to_owned(),
+                }],
+            }],
+        };
+        let glyphs: GlyphMap =
+            serde_json::from_str(r#"{"real":{"H":"h"},"decoy":{"H":"*"}}"#).unwrap();
+
+        assert_eq!(glyphs.glyph(Letter::H, true), "h");
+        assert_eq!(glyphs.glyph(Letter::H, false), "*");
+        assert_eq!(glyphs.glyph(Letter::I, false), "I");
+
+        let html = compile_grid(&grid, &glyphs);
+        let cells = html.matches("<td").count();
+        assert_eq!(cells, 3);
+        assert!(html.contains("letter-on-0-0\" data-token=\"0\" data-phrase=\"0\">h<"));
+        assert!(html.contains("letter-on-1-0\" data-token=\"0\" data-phrase=\"0\">I<"));
+        assert!(html.contains("letter-on-2-0\">*<"));
+    }
+
+    #[test]
+    fn data_attributes() {
+        // "HI" across the first row and "IT" down the second column, crossing at the "I"
+        let word = |letters: Vec<(i16, i16)>, text: &str| GridOutputWord {
+            letters,
+            text: text.to_owned(),
+        };
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: vec![
+                vec![Some(Letter::H), Some(Letter::I)],
+                vec![None, Some(Letter::T)],
+            ],
+            grid: vec![vec![Letter::H, Letter::I], vec![Letter::X, Letter::T]],
+            fixed_cells: vec![],
+            phrases: vec![
+                GridOutputPhrase {
+                    words: vec![word(vec![(0, 0), (1, 0)], "HI")],
+                },
+                GridOutputPhrase {
+                    words: vec![word(vec![(1, 0), (1, 1)], "IT")],
+                },
+                GridOutputPhrase {
+                    words: vec![word(vec![(0, 0), (1, 0)], "HI")],
+                },
+            ],
+        };
+
+        let html = compile_grid(&grid, &GlyphMap::default());
+        assert!(html.contains("letter-on-0-0\" data-token=\"0\" data-phrase=\"0 2\">H<"));
+        assert!(html.contains("letter-on-1-0\" data-token=\"0 1\" data-phrase=\"0 1 2\">I<"));
+        assert!(html.contains("letter-on-1-1\" data-token=\"1\" data-phrase=\"1\">T<"));
+        assert!(html.contains("letter-on-0-1\">X<"));
+    }
+}
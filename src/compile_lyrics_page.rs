@@ -2,13 +2,15 @@ mod keyframes;
 
 use std::collections::BTreeMap;
 use std::fmt;
+use std::fmt::Write;
+use std::str::FromStr;
 
-use anyhow::{ensure, Result};
+use anyhow::{anyhow, ensure, Error, Result};
 use itertools::Itertools;
 
-use crate::build_grid::compile_html::compile_grid;
+use crate::build_grid::compile_html::{compile_grid, GlyphMap};
 use crate::compile_lyrics_page::keyframes::{extract_frames, Keyframes};
-use crate::{GridOutput, LyricsPuzzleInput};
+use crate::models::io::{GridOutput, LyricsPuzzleInput};
 
 /// Configure the animation curve timings. Measurements are in `ms`
 #[derive(Debug, Clone, Copy)]
@@ -17,9 +19,40 @@ pub struct AnimationConfig {
     pub margin_before: i32,
     pub margin_after: i32,
     pub ease_out: i32,
-    /// The ratio of the phrase duration that is dedicated to animate the letter as a incoming wave
-    pub letters_entering: f64,
+    /// How long the letters of a phrase take to animate as an incoming wave
+    pub timing_mode: TimingMode,
     pub discrete_time_step: i32,
+    pub reveal_direction: RevealDirection,
+}
+
+/// Determine in which order the letters of a phrase are revealed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RevealDirection {
+    /// In the order of the words and of their letters
+    Reading,
+    /// From top to bottom, then from left to right
+    GridPosition,
+    /// From the closest to the farthest letter to the given cell `(x, y)`
+    Radial(i16, i16),
+}
+
+/// Determine the duration of the incoming wave of letters of each phrase
+#[derive(Debug, Clone, Copy)]
+pub enum TimingMode {
+    /// The ratio of the phrase duration that is dedicated to animate the letters
+    Ratio(f64),
+    /// The number of letters revealed per second. The resulting duration is clamped to the phrase
+    /// duration
+    Rate(f64),
+}
+
+/// The format of the lyrics puzzle output
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LyricsFormat {
+    /// A standalone HTML page that animates the grid
+    Html,
+    /// A WebVTT file with one cue per phrase
+    Vtt,
 }
 
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
@@ -41,15 +74,142 @@ pub fn compile_lyrics_page(
     phrases: &LyricsPuzzleInput,
     grid: &GridOutput,
     config: AnimationConfig,
+    glyphs: &GlyphMap,
 ) -> Result<String> {
+    ensure!(
+        !phrases.phrases.is_empty(),
+        "The lyrics have no phrases, so there is nothing to animate"
+    );
+
     let page = include_str!("compile_lyrics_page/template.html");
     let page = page.replacen("${STYLE}", &compile_css(phrases, grid, config)?, 1);
-    let page = page.replacen("${GRID}", &compile_grid(grid), 1);
+    let page = page.replacen("${GRID}", &compile_grid(grid, glyphs), 1);
     let page = page.replacen("${VIDEO_ID}", &phrases.video_id, 1);
 
     Ok(page)
 }
 
+/// Check that the lyrics and the grid are consistent, and that the timings are ordered. Return
+/// every problem found, instead of stopping at the first one.
+pub fn validate(phrases: &LyricsPuzzleInput, grid: &GridOutput) -> Vec<String> {
+    let mut problems = vec![];
+    if phrases.phrases.is_empty() {
+        problems.push("The lyrics have no phrases".to_owned());
+    }
+    if phrases.phrases.len() != grid.phrases.len() {
+        problems.push(format!(
+            "The lyrics have {} phrases, but the grid has {}",
+            phrases.phrases.len(),
+            grid.phrases.len()
+        ));
+    }
+
+    let mut previous_start = 0;
+    for (i, lyrics_phrase) in phrases.phrases.iter().enumerate() {
+        let n = i + 1;
+        if lyrics_phrase.start > lyrics_phrase.end {
+            problems.push(format!("Phrase {} ends before it starts", n));
+        }
+        if lyrics_phrase.start < previous_start {
+            problems.push(format!("Phrase {} starts before the previous one", n));
+        }
+        if lyrics_phrase.end > phrases.total_duration {
+            problems.push(format!("Phrase {} ends after the total duration", n));
+        }
+        previous_start = lyrics_phrase.start;
+
+        let grid_phrase = match grid.phrases.get(i) {
+            None => continue,
+            Some(grid_phrase) => grid_phrase,
+        };
+        if lyrics_phrase.texts.len() != grid_phrase.words.len() {
+            problems.push(format!(
+                "Phrase {} has {} words in the lyrics, but {} in the grid",
+                n,
+                lyrics_phrase.texts.len(),
+                grid_phrase.words.len()
+            ));
+            continue;
+        }
+        for (text, word) in lyrics_phrase.texts.iter().zip(&grid_phrase.words) {
+            if text.letters().len() != word.letters.len() {
+                problems.push(format!(
+                    "Phrase {} has the word {} with {} letters in the grid",
+                    n,
+                    text,
+                    word.letters.len()
+                ));
+            }
+        }
+    }
+
+    problems
+}
+
+/// Compile the phrases as WebVTT cues. When the grid is given, each cue also lists the cells of
+/// its letters, as `x,y` pairs.
+pub fn compile_vtt(phrases: &LyricsPuzzleInput, grid: Option<&GridOutput>) -> Result<String> {
+    if let Some(grid) = grid {
+        ensure!(phrases.phrases.len() == grid.phrases.len());
+    }
+
+    let mut vtt = String::from("WEBVTT\n");
+    for (i, phrase) in phrases.phrases.iter().enumerate() {
+        ensure!(
+            phrase.start <= phrase.end,
+            "Phrase {} ends before it starts",
+            i + 1
+        );
+        write!(
+            vtt,
+            "\n{}\n{} --> {}\n{}\n",
+            i + 1,
+            VttTimestamp(phrase.start),
+            VttTimestamp(phrase.end),
+            phrase.texts.iter().format(" ")
+        )?;
+        if let Some(grid) = grid {
+            let letters = grid.phrases[i].words.iter().flat_map(|word| &word.letters);
+            writeln!(
+                vtt,
+                "{}",
+                letters.format_with(" ", |(x, y), f| f(&format_args!("{},{}", x, y)))
+            )?;
+        }
+    }
+
+    Ok(vtt)
+}
+
+/// A time in `ms`, formatted as `hh:mm:ss.ttt`
+struct VttTimestamp(i32);
+
+impl fmt::Display for VttTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ms = self.0.max(0);
+        write!(
+            f,
+            "{:02}:{:02}:{:02}.{:03}",
+            ms / 3_600_000,
+            ms / 60_000 % 60,
+            ms / 1000 % 60,
+            ms % 1000
+        )
+    }
+}
+
+impl FromStr for LyricsFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "html" => Ok(LyricsFormat::Html),
+            "vtt" => Ok(LyricsFormat::Vtt),
+            _ => Err(anyhow!("Lyrics format was not recognized: {}", s)),
+        }
+    }
+}
+
 fn compile_css(
     phrases: &LyricsPuzzleInput,
     grid: &GridOutput,
@@ -62,13 +222,19 @@ fn compile_css(
         ensure!(lyrics_phrase.texts.len() == grid_phrase.words.len());
         let end_ease_in = lyrics_phrase.start - config.margin_before;
         let start_ease_out = lyrics_phrase.end + config.margin_after;
-        let entering_duration =
-            ((start_ease_out - end_ease_in) as f64 * config.letters_entering).floor();
-
-        let letters = grid_phrase.words.iter().flat_map(|word| &word.letters);
-        let entering_step = entering_duration / (letters.clone().count() - 1) as f64;
+        let letters = config.reveal_direction.sort(
+            grid_phrase
+                .words
+                .iter()
+                .flat_map(|word| &word.letters)
+                .copied()
+                .collect(),
+        );
+        let entering_step = config
+            .timing_mode
+            .entering_step(start_ease_out - end_ease_in, letters.len());
 
-        for (i, &letter) in letters.enumerate() {
+        for (i, letter) in letters.into_iter().enumerate() {
             let end_ease_in = end_ease_in + (i as f64 * entering_step) as i32;
             timelines_per_letter
                 .entry(letter)
@@ -118,6 +284,59 @@ fn compile_css(
     Ok(letter_animations.into_iter().format("\n").to_string())
 }
 
+impl TimingMode {
+    /// Return the delay between the reveal of two consecutive letters of a phrase that has
+    /// `num_letters` letters and is displayed for `window` ms. A single letter has no delay.
+    fn entering_step(self, window: i32, num_letters: usize) -> f64 {
+        let entering_duration = match self {
+            TimingMode::Ratio(ratio) => (window as f64 * ratio).floor(),
+            TimingMode::Rate(rate) => (num_letters as f64 / rate * 1000.0)
+                .floor()
+                .min(window as f64),
+        };
+
+        if num_letters <= 1 {
+            return 0.0;
+        }
+
+        entering_duration / (num_letters - 1) as f64
+    }
+}
+
+impl RevealDirection {
+    /// Sort the letter positions of a phrase in the order they should be revealed
+    fn sort(self, mut letters: Vec<(i16, i16)>) -> Vec<(i16, i16)> {
+        match self {
+            RevealDirection::Reading => {}
+            RevealDirection::GridPosition => letters.sort_by_key(|&(x, y)| (y, x)),
+            RevealDirection::Radial(center_x, center_y) => letters.sort_by_key(|&(x, y)| {
+                let dx = (x - center_x) as i32;
+                let dy = (y - center_y) as i32;
+                dx * dx + dy * dy
+            }),
+        }
+        letters
+    }
+}
+
+impl FromStr for RevealDirection {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "reading" => Ok(RevealDirection::Reading),
+            "grid-position" => Ok(RevealDirection::GridPosition),
+            _ => {
+                let center = s.strip_prefix("radial:").and_then(|center| {
+                    let (x, y) = center.split_once(',')?;
+                    Some(RevealDirection::Radial(x.parse().ok()?, y.parse().ok()?))
+                });
+                center.ok_or_else(|| anyhow!("Reveal direction was not recognized: {}", s))
+            }
+        }
+    }
+}
+
 impl Animation {
     fn get(self, at: i32) -> f64 {
         fn interpolate(x1: i32, x2: i32, y1: f64, y2: f64, p: i32) -> f64 {
@@ -156,3 +375,181 @@ impl fmt::Display for LettersAnimation {
         writeln!(f, "{}", self.keyframes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::io::{GridOutputPhrase, GridOutputWord, LyricsPhrase, GRID_OUTPUT_VERSION};
+    use crate::models::letter::Letter;
+
+    #[test]
+    fn compile_vtt() {
+        let vtt = super::compile_vtt(&LyricsPuzzleInput::example(), None).unwrap();
+
+        let mut lines = vtt.lines();
+        assert_eq!(lines.next(), Some("WEBVTT"));
+        let mut previous_end = String::new();
+        let mut num_cues = 0;
+        for line in lines.filter(|line| line.contains(" --> ")) {
+            let (start, end) = line.split_once(" --> ").unwrap();
+            for timestamp in [start, end] {
+                let parts = timestamp.split(&[':', '.'][..]).collect_vec();
+                assert_eq!(
+                    parts.iter().map(|part| part.len()).collect_vec(),
+                    [2, 2, 2, 3]
+                );
+                assert!(parts.iter().all(|part| part.parse::<u32>().is_ok()));
+            }
+            // Fixed width timestamps can be compared as strings
+            assert!(previous_end.as_str() <= start && start <= end);
+            previous_end = end.to_owned();
+            num_cues += 1;
+        }
+        assert_eq!(num_cues, 2);
+        assert!(vtt.contains("00:00:01.000 --> 00:00:02.500\nHELLO WORLD\n"));
+        assert_eq!(VttTimestamp(3_723_004).to_string(), "01:02:03.004");
+    }
+
+    fn test_config() -> AnimationConfig {
+        AnimationConfig {
+            ease_in: 250,
+            margin_before: 0,
+            margin_after: 0,
+            ease_out: 100,
+            timing_mode: TimingMode::Ratio(0.5),
+            discrete_time_step: 42,
+            reveal_direction: RevealDirection::Reading,
+        }
+    }
+
+    #[test]
+    fn reveal_direction() {
+        let letters = vec![(2, 1), (0, 1), (1, 0), (3, 0)];
+
+        assert_eq!(RevealDirection::Reading.sort(letters.clone()), letters);
+        assert_eq!(
+            RevealDirection::GridPosition.sort(letters.clone()),
+            [(1, 0), (3, 0), (0, 1), (2, 1)]
+        );
+        assert_eq!(
+            "radial:3,1"
+                .parse::<RevealDirection>()
+                .unwrap()
+                .sort(letters),
+            [(2, 1), (3, 0), (1, 0), (0, 1)]
+        );
+        assert!("radial:3".parse::<RevealDirection>().is_err());
+    }
+
+    #[test]
+    fn validate() {
+        let phrases = LyricsPuzzleInput::example();
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: vec![],
+            grid: vec![],
+            fixed_cells: vec![],
+            phrases: vec![GridOutputPhrase {
+                words: vec![
+                    GridOutputWord {
+                        letters: vec![(0, 0), (1, 0), (2, 0), (3, 0), (4, 0)],
+                        text: "HELLO".to_owned(),
+                    },
+                    GridOutputWord {
+                        letters: vec![(0, 1), (1, 1), (2, 1), (3, 1)],
+                        text: "WORL".to_owned(),
+                    },
+                ],
+            }],
+        };
+        let mut late_phrases = phrases.clone();
+        late_phrases.phrases[1].start = 500;
+
+        assert_eq!(
+            super::validate(&late_phrases, &grid),
+            [
+                "The lyrics have 2 phrases, but the grid has 1",
+                "Phrase 1 has the word WORLD with 4 letters in the grid",
+                "Phrase 2 starts before the previous one",
+            ]
+        );
+    }
+
+    #[test]
+    fn entering_step_ratio() {
+        let mode = TimingMode::Ratio(0.5);
+        assert_eq!(mode.entering_step(1000, 11), 50.0);
+        assert_eq!(mode.entering_step(2000, 11), 100.0);
+    }
+
+    #[test]
+    fn entering_step_rate() {
+        // 10 letters per second: 11 letters enter in 1100ms, so the 10 gaps between them are
+        // 110ms each, regardless of the phrase duration
+        let mode = TimingMode::Rate(10.0);
+        assert_eq!(mode.entering_step(5000, 11), 110.0);
+        assert_eq!(mode.entering_step(10000, 11), 110.0);
+
+        // Clamped to the phrase duration
+        assert_eq!(mode.entering_step(500, 11), 50.0);
+    }
+
+    #[test]
+    fn empty_lyrics() {
+        let phrases = LyricsPuzzleInput {
+            phrases: vec![],
+            ..LyricsPuzzleInput::example()
+        };
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: vec![],
+            grid: vec![],
+            fixed_cells: vec![],
+            phrases: vec![],
+        };
+
+        let error =
+            compile_lyrics_page(&phrases, &grid, test_config(), &GlyphMap::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "The lyrics have no phrases, so there is nothing to animate"
+        );
+    }
+
+    #[test]
+    fn one_letter_phrase() {
+        let phrases = LyricsPuzzleInput {
+            video_id: "YOUTUBE_VIDEO_ID".to_owned(),
+            total_duration: 3_000,
+            phrases: vec![LyricsPhrase {
+                texts: vec!["A".parse().unwrap()],
+                start: 1_000,
+                end: 2_000,
+            }],
+        };
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: vec![vec![Some(Letter::A)]],
+            grid: vec![vec![Letter::A]],
+            fixed_cells: vec![],
+            phrases: vec![GridOutputPhrase {
+                words: vec![GridOutputWord {
+                    letters: vec![(0, 0)],
+                    text: "A".to_owned(),
+                }],
+            }],
+        };
+
+        for timing_mode in [TimingMode::Ratio(0.5), TimingMode::Rate(10.0)] {
+            assert_eq!(timing_mode.entering_step(1000, 1), 0.0);
+
+            let config = AnimationConfig {
+                timing_mode,
+                ..test_config()
+            };
+            let css = compile_css(&phrases, &grid, config).unwrap();
+            assert!(css.contains(".letter-on-0-0"));
+            assert!(!css.contains("NaN") && !css.contains("inf"));
+        }
+    }
+}
@@ -1,24 +1,552 @@
 use crate::models::language::Language;
 use crate::models::phrase::TimePhrase;
-use crate::models::time::Time;
+use crate::models::text::Text;
+use crate::models::time::{Rounding, Time, TimeOffset, TimeRange};
+use anyhow::{anyhow, ensure, Error, Result};
+use itertools::{Either, Itertools};
+use regex::Regex;
+use std::collections::BTreeSet;
+use std::str::FromStr;
 
+pub mod dutch;
 pub mod english;
 pub mod french;
 pub mod german;
+pub mod greek;
+pub mod italian;
 pub mod portuguese;
+pub mod spanish;
+#[cfg(test)]
+pub mod test_language;
 
-pub fn generate_phrases(language_specs: &[(Language, i32)]) -> Vec<TimePhrase> {
+/// Determine how the next hour is computed, when a phrase refers to it, like "QUARTER TO ONE"
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HourRollover {
+    /// The next hour wraps at 12: both 11:45 and 23:45 refer to the hour 0. This fits 12-hour
+    /// dials, in which midday and midnight are not distinguished, so the hours 0 and 12 are both
+    /// spelled as a neutral twelve, like "TWELVE O CLOCK".
+    Twelve,
+    /// The next hour wraps at 24: 11:45 refers to midday and 23:45 refers to midnight
+    TwentyFour,
+}
+
+/// Options that change how a time is spelled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpellOptions {
+    pub rollover: HourRollover,
+    /// Add the period of the day, like "DA TARDE". Only supported by Portuguese.
+    pub period_suffix: bool,
+}
+
+/// Determine which phrases to generate for a language, parsed from a tag like "Portuguese+period:5"
+#[derive(Debug, Clone, Copy)]
+pub struct LanguageSpec {
+    pub language: Language,
+    /// The step between the generated times, in minutes
+    pub precision: i32,
+    /// See [`SpellOptions::period_suffix`]
+    pub period_suffix: bool,
+}
+
+impl SpellOptions {
+    pub fn new(rollover: HourRollover) -> Self {
+        SpellOptions {
+            rollover,
+            period_suffix: false,
+        }
+    }
+}
+
+impl LanguageSpec {
+    pub fn new(language: Language, precision: i32) -> Self {
+        LanguageSpec {
+            language,
+            precision,
+            period_suffix: false,
+        }
+    }
+}
+
+impl FromStr for LanguageSpec {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tag, precision) = match s.split_once(':') {
+            None => (s, 1),
+            Some((tag, precision)) => (tag, precision.parse()?),
+        };
+
+        let (language, period_suffix) = match tag.split_once('+') {
+            None => (tag, false),
+            Some((language, "period")) => (language, true),
+            Some((_, option)) => {
+                return Err(anyhow!("Language option was not recognized: {}", option))
+            }
+        };
+        let language = language.parse()?;
+        ensure!(
+            !period_suffix || matches!(language, Language::Portuguese),
+            "The period option is only supported by Portuguese"
+        );
+
+        Ok(LanguageSpec {
+            language,
+            precision,
+            period_suffix,
+        })
+    }
+}
+
+/// Generate the phrases of each language, for the times in `range`. Each phrase is labelled with
+/// its time, but spells the time rounded by `rounding` and shifted by `offset`.
+///
+/// When rounding, consecutive times often spell the same phrase, like 09:00 to 09:04 rounded down
+/// to 5 minutes: only the first of them is kept, which is when the phrase starts being shown.
+pub fn generate_phrases(
+    language_specs: &[LanguageSpec],
+    rollover: HourRollover,
+    offset: TimeOffset,
+    range: TimeRange,
+    rounding: Option<Rounding>,
+) -> Vec<TimePhrase> {
     let mut phrases = vec![];
 
-    for &(language, precision) in language_specs {
-        for time in Time::all_times().step_by(precision as usize) {
-            phrases.push(TimePhrase {
-                language,
-                time,
-                texts: language.spell(time),
-            });
+    for spec in language_specs {
+        let times = Time::all_times()
+            .step_by(spec.precision as usize)
+            .filter(|&time| range.contains(time))
+            .collect_vec();
+        let options = SpellOptions {
+            rollover,
+            period_suffix: spec.period_suffix,
+        };
+        let shifted_times = times
+            .iter()
+            .map(|&time| match rounding {
+                None => time,
+                Some(rounding) => time.rounded(rounding),
+            })
+            .map(|time| time.shifted(offset))
+            .collect_vec();
+        let shifted_phrases = generate_phrases_at(spec.language, &shifted_times, options);
+        let mut language_phrases = shifted_phrases
+            .into_iter()
+            .zip(times)
+            .map(|(phrase, time)| TimePhrase { time, ..phrase })
+            .collect_vec();
+        if rounding.is_some() {
+            language_phrases.dedup_by(|phrase, previous| phrase.texts == previous.texts);
         }
+        phrases.extend(language_phrases);
     }
 
     phrases
 }
+
+/// Spell the phrase with the letters A-Z only, since the grid has no other letters: the accents and
+/// umlauts are dropped, like "Ü" to "U", "ß" is expanded to "SS" and the apostrophes separate
+/// words, like "L'UNA" to "L UNA"
+pub fn normalize_letters(phrase: &str) -> String {
+    phrase
+        .chars()
+        .flat_map(char::to_uppercase)
+        .flat_map(|c| {
+            let normalized = match c {
+                'ẞ' => "SS",
+                '\'' | '’' => " ",
+                'À' | 'Á' | 'Â' | 'Ã' | 'Ä' | 'Å' => "A",
+                'Ç' => "C",
+                'È' | 'É' | 'Ê' | 'Ë' => "E",
+                'Ì' | 'Í' | 'Î' | 'Ï' => "I",
+                'Ñ' => "N",
+                'Ò' | 'Ó' | 'Ô' | 'Õ' | 'Ö' => "O",
+                'Ù' | 'Ú' | 'Û' | 'Ü' => "U",
+                'Ý' => "Y",
+                _ => return Either::Left(std::iter::once(c)),
+            };
+            Either::Right(normalized.chars())
+        })
+        .collect()
+}
+
+/// Return whether the precision, in minutes, divides an hour evenly. Only these precisions hit the
+/// same minutes in every hour: 1, 2, 3, 4, 5, 6, 10, 12, 15, 20, 30 and 60.
+pub fn is_clean_precision(precision: i32) -> bool {
+    precision > 0 && 60 % precision == 0
+}
+
+/// Warn when the precision does not divide an hour evenly, or fail if `strict` is set
+pub fn check_precision(precision: i32, strict: bool) -> Result<()> {
+    ensure!(
+        precision > 0,
+        "The precision must be positive: {}",
+        precision
+    );
+    if !is_clean_precision(precision) {
+        let message = format!(
+            "The precision of {} minutes does not divide an hour evenly, so each hour will have \
+            different minutes",
+            precision
+        );
+        ensure!(!strict, message);
+        log::warn!("{}", message);
+    }
+    Ok(())
+}
+
+/// Generate the phrases for an arbitrary set of times
+pub fn generate_phrases_at(
+    language: Language,
+    times: &[Time],
+    options: SpellOptions,
+) -> Vec<TimePhrase> {
+    times
+        .iter()
+        .map(|&time| TimePhrase {
+            language,
+            time,
+            texts: language.spell(time, options),
+        })
+        .collect()
+}
+
+impl HourRollover {
+    /// Return the hour that follows the given one
+    pub fn next_hour(self, hours: u8) -> u8 {
+        match self {
+            HourRollover::Twelve => (hours + 1) % 12,
+            HourRollover::TwentyFour => (hours + 1) % 24,
+        }
+    }
+}
+
+impl FromStr for HourRollover {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "12" => Ok(HourRollover::Twelve),
+            "24" => Ok(HourRollover::TwentyFour),
+            _ => Err(anyhow!("Hour rollover was not recognized: {}", s)),
+        }
+    }
+}
+
+/// Add the `prefix` words before and the `suffix` words after each phrase
+pub fn decorate_phrases(phrases: &mut [TimePhrase], prefix: &[Text], suffix: &[Text]) {
+    for phrase in phrases {
+        phrase.texts.splice(0..0, prefix.iter().cloned());
+        phrase.texts.extend(suffix.iter().cloned());
+    }
+}
+
+/// Keep only the phrases whose text matches the regular expression. The text is matched with its
+/// words joined by single spaces, in uppercase, like "IT IS HALF PAST ONE".
+pub fn filter_phrases(phrases: &mut Vec<TimePhrase>, filter: &Regex) {
+    phrases.retain(|phrase| filter.is_match(&phrase.texts.iter().join(" ")));
+}
+
+/// Remove the phrases that spell the same words as a previous phrase of the same language, like
+/// 01:00 and 13:00 in English, keeping the first one. Return how many were removed.
+pub fn dedup_phrases(phrases: &mut Vec<TimePhrase>) -> usize {
+    let num_phrases = phrases.len();
+    let mut seen = BTreeSet::new();
+    phrases.retain(|phrase| seen.insert((phrase.language.tag(), phrase.texts.clone())));
+    num_phrases - phrases.len()
+}
+
+/// Split the phrases into consecutive chunks with roughly the same size and at most
+/// `max_per_chunk` phrases each. Concatenating the chunks gives back the original phrases.
+pub fn split_phrases(phrases: Vec<TimePhrase>, max_per_chunk: usize) -> Vec<Vec<TimePhrase>> {
+    assert!(max_per_chunk > 0);
+    if phrases.is_empty() {
+        return vec![];
+    }
+    let num_chunks = (phrases.len() - 1) / max_per_chunk + 1;
+    let chunk_size = (phrases.len() - 1) / num_chunks + 1;
+
+    phrases
+        .chunks(chunk_size)
+        .map(|chunk| chunk.to_vec())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::time::RoundingMode;
+
+    #[test]
+    fn precision() {
+        assert!(is_clean_precision(15));
+        assert!(check_precision(15, true).is_ok());
+
+        assert!(!is_clean_precision(7));
+        assert!(check_precision(7, false).is_ok());
+        assert!(check_precision(7, true).is_err());
+
+        assert!(check_precision(0, false).is_err());
+    }
+
+    #[test]
+    fn normalize() {
+        assert_eq!(normalize_letters("DREIßIG"), "DREISSIG");
+        assert_eq!(normalize_letters("GROẞ"), "GROSS");
+        assert_eq!(normalize_letters("FÜNF NACH ZWÖLF"), "FUNF NACH ZWOLF");
+        assert_eq!(normalize_letters("Mediodía"), "MEDIODIA");
+        assert_eq!(normalize_letters("È L'UNA"), "E L UNA");
+    }
+
+    #[test]
+    fn split() {
+        let phrases = generate_phrases(
+            &[LanguageSpec::new(Language::English, 5)],
+            HourRollover::TwentyFour,
+            TimeOffset::default(),
+            TimeRange::default(),
+            None,
+        );
+        let num_phrases = phrases.len();
+        let expected = phrases
+            .iter()
+            .map(|phrase| phrase.time.to_string())
+            .collect::<Vec<_>>();
+
+        let chunks = split_phrases(phrases, 100);
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(
+            chunks.iter().map(Vec::len).collect::<Vec<_>>(),
+            vec![96, 96, 96]
+        );
+        assert_eq!(num_phrases, 288);
+
+        let actual = chunks
+            .into_iter()
+            .flatten()
+            .map(|phrase| phrase.time.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn hour_rollover() {
+        let spell = |language: Language, hours, rollover| {
+            let texts = language.spell(Time::new(hours, 45), SpellOptions::new(rollover));
+            texts
+                .iter()
+                .map(|text| text.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        };
+        let cases = [
+            (
+                Language::English,
+                [
+                    "QUARTER TO MIDDAY",
+                    "QUARTER TO MIDNIGHT",
+                    "QUARTER TO TWELVE",
+                ],
+            ),
+            (
+                Language::French,
+                [
+                    "MIDI MOINS LE QUART",
+                    "MINUIT MOINS LE QUART",
+                    "DOUZE HEURES MOINS LE QUART",
+                ],
+            ),
+            (
+                Language::German,
+                [
+                    "VIERTEL VOR MITTAG",
+                    "VIERTEL VOR MITTERNACHT",
+                    "VIERTEL VOR ZWOLF",
+                ],
+            ),
+            (
+                Language::Portuguese,
+                [
+                    "QUINZE PARA O MEIO DIA",
+                    "QUINZE PARA A MEIA NOITE",
+                    "QUINZE PARA AS DOZE",
+                ],
+            ),
+        ];
+
+        for (language, [at_11_24h, at_23_24h, at_11_and_23_12h]) in cases {
+            assert_eq!(spell(language, 11, HourRollover::TwentyFour), at_11_24h);
+            assert_eq!(spell(language, 23, HourRollover::TwentyFour), at_23_24h);
+            assert_eq!(spell(language, 11, HourRollover::Twelve), at_11_and_23_12h);
+            assert_eq!(spell(language, 23, HourRollover::Twelve), at_11_and_23_12h);
+        }
+
+        // With a 12-hour dial, the current hour is not named either
+        let twelve_o_clock = |language: Language, hours| {
+            language
+                .spell(Time::new(hours, 0), SpellOptions::new(HourRollover::Twelve))
+                .iter()
+                .map(|text| text.to_string())
+                .join(" ")
+        };
+        for language in Language::all() {
+            assert_eq!(twelve_o_clock(language, 0), twelve_o_clock(language, 12));
+        }
+        assert_eq!(twelve_o_clock(Language::English, 12), "TWELVE O CLOCK");
+        assert_eq!(twelve_o_clock(Language::French, 0), "DOUZE HEURES");
+        assert_eq!(twelve_o_clock(Language::German, 12), "UM ZWOLF");
+        assert_eq!(twelve_o_clock(Language::Portuguese, 12), "DOZE HORAS");
+    }
+
+    #[test]
+    fn filter() {
+        let mut phrases = generate_phrases(
+            &[LanguageSpec::new(Language::English, 15)],
+            HourRollover::TwentyFour,
+            TimeOffset::default(),
+            TimeRange::default(),
+            None,
+        );
+        filter_phrases(&mut phrases, &Regex::new(r"\bHALF\b").unwrap());
+
+        assert_eq!(phrases.len(), 24);
+        for phrase in phrases {
+            assert_eq!(phrase.time.minutes(), 30);
+        }
+    }
+
+    #[test]
+    fn dedup() {
+        let generate = |rollover| {
+            let mut phrases = generate_phrases(
+                &[LanguageSpec::new(Language::English, 15)],
+                rollover,
+                TimeOffset::default(),
+                TimeRange::default(),
+                None,
+            );
+            let removed = dedup_phrases(&mut phrases);
+            (phrases, removed)
+        };
+        let has_time = |phrases: &[TimePhrase], hours| {
+            phrases
+                .iter()
+                .any(|phrase| phrase.time.hours() == hours && phrase.time.minutes() == 45)
+        };
+
+        // With 12-hour rollover, the afternoon spells the same as the morning, even at midday
+        let (phrases, removed) = generate(HourRollover::Twelve);
+        assert_eq!(removed, 48);
+        assert_eq!(phrases.len(), 48);
+        assert!(has_time(&phrases, 11));
+        assert!(!has_time(&phrases, 23));
+
+        // With 24-hour rollover, "QUARTER TO MIDDAY" and "QUARTER TO MIDNIGHT" are kept apart
+        let (phrases, removed) = generate(HourRollover::TwentyFour);
+        assert_eq!(removed, 44);
+        assert!(has_time(&phrases, 11));
+        assert!(has_time(&phrases, 23));
+    }
+
+    #[test]
+    fn offset() {
+        let spec = LanguageSpec::new(Language::English, 15);
+        let phrases = generate_phrases(
+            &[spec],
+            HourRollover::TwentyFour,
+            TimeOffset::default(),
+            TimeRange::default(),
+            None,
+        );
+        let shifted_phrases = generate_phrases(
+            &[spec],
+            HourRollover::TwentyFour,
+            "+05:30".parse().unwrap(),
+            TimeRange::default(),
+            None,
+        );
+        assert_eq!(phrases.len(), shifted_phrases.len());
+
+        let find = |phrases: &[TimePhrase], hours, minutes| {
+            phrases
+                .iter()
+                .find(|phrase| phrase.time.hours() == hours && phrase.time.minutes() == minutes)
+                .unwrap()
+                .texts
+                .clone()
+        };
+        assert_eq!(find(&shifted_phrases, 20, 0), find(&phrases, 1, 30));
+        assert_eq!(find(&shifted_phrases, 10, 15), find(&phrases, 15, 45));
+    }
+
+    #[test]
+    fn range() {
+        let range = TimeRange::new(Time::new(9, 0), Time::new(17, 0)).unwrap();
+        let phrases = generate_phrases(
+            &[LanguageSpec::new(Language::English, 15)],
+            HourRollover::TwentyFour,
+            TimeOffset::default(),
+            range,
+            None,
+        );
+
+        assert_eq!(phrases.len(), 33);
+        assert_eq!(phrases[0].time.to_string(), "09:00");
+        assert_eq!(phrases[32].time.to_string(), "17:00");
+    }
+
+    #[test]
+    fn rounding() {
+        let generate = |mode| {
+            generate_phrases(
+                &[LanguageSpec::new(Language::English, 1)],
+                HourRollover::TwentyFour,
+                TimeOffset::default(),
+                TimeRange::new(Time::new(9, 0), Time::new(9, 59)).unwrap(),
+                Some(Rounding { step: 5, mode }),
+            )
+        };
+
+        let phrases = generate(RoundingMode::Down);
+        assert_eq!(phrases.len(), 12);
+        assert_eq!(
+            phrases
+                .iter()
+                .map(|phrase| &phrase.texts)
+                .collect::<BTreeSet<_>>()
+                .len(),
+            12
+        );
+        for phrase in &phrases {
+            assert_eq!(phrase.time.minutes() % 5, 0);
+        }
+
+        // The phrases start being shown 2 minutes and a half before their time
+        let phrases = generate(RoundingMode::Nearest);
+        let times = phrases
+            .iter()
+            .map(|phrase| phrase.time.to_string())
+            .collect_vec();
+        assert_eq!(&times[..3], ["09:00", "09:03", "09:08"]);
+        assert_eq!(times.last().unwrap(), "09:58");
+        assert_eq!(phrases.len(), 13);
+    }
+
+    #[test]
+    fn decorate() {
+        let mut phrases = generate_phrases(
+            &[LanguageSpec::new(Language::French, 15)],
+            HourRollover::TwentyFour,
+            TimeOffset::default(),
+            TimeRange::default(),
+            None,
+        );
+        let prefix = vec!["IL".parse().unwrap(), "EST".parse().unwrap()];
+        let suffix = vec!["PILE".parse().unwrap()];
+        decorate_phrases(&mut phrases, &prefix, &suffix);
+
+        for phrase in phrases {
+            assert!(phrase.texts.starts_with(&prefix));
+            assert!(phrase.texts.ends_with(&suffix));
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use crate::generate_phrases::{normalize_letters, HourRollover};
+use crate::models::time::Time;
+
+/// Spell the time in Dutch. Like in German, the minutes close to the half hour are relative to it:
+/// "TIEN VOOR HALF DRIE". The diaereses are dropped, like "TWEEËNTWINTIG" to "TWEEENTWINTIG".
+pub fn spell(time: Time, rollover: HourRollover) -> String {
+    let hours = time.hours();
+    let minutes = time.minutes();
+    let next_hour = rollover.next_hour(hours);
+    let phrase = match minutes {
+        0 => spell_hours(hours, true, rollover),
+        1..=14 => format!(
+            "{} OVER {}",
+            spell_number(minutes),
+            spell_hours(hours, false, rollover)
+        ),
+        15 => format!("KWART OVER {}", spell_hours(hours, false, rollover)),
+        16..=29 => format!(
+            "{} VOOR HALF {}",
+            spell_number(30 - minutes),
+            spell_hours(next_hour, false, rollover)
+        ),
+        30 => format!("HALF {}", spell_hours(next_hour, false, rollover)),
+        31..=44 => format!(
+            "{} OVER HALF {}",
+            spell_number(minutes - 30),
+            spell_hours(next_hour, false, rollover)
+        ),
+        45 => format!("KWART VOOR {}", spell_hours(next_hour, false, rollover)),
+        46..=59 => format!(
+            "{} VOOR {}",
+            spell_number(60 - minutes),
+            spell_hours(next_hour, false, rollover)
+        ),
+        _ => unreachable!(),
+    };
+
+    normalize_letters(&phrase)
+}
+
+fn spell_hours(n: u8, include_uur: bool, rollover: HourRollover) -> String {
+    assert!(n < 24);
+
+    match (n, include_uur) {
+        (0, include_uur) if rollover == HourRollover::Twelve => {
+            spell_hours(12, include_uur, rollover)
+        }
+        (0, _) => "MIDDERNACHT".to_owned(),
+        (n, true) if n <= 12 => format!("{} UUR", spell_number(n)),
+        (n, false) if n <= 12 => spell_number(n),
+        (n, include_uur) => spell_hours(n - 12, include_uur, rollover),
+    }
+}
+
+fn spell_number(n: u8) -> String {
+    assert!(n < 30);
+
+    let solo = &[
+        "",
+        "EEN",
+        "TWEE",
+        "DRIE",
+        "VIER",
+        "VIJF",
+        "ZES",
+        "ZEVEN",
+        "ACHT",
+        "NEGEN",
+        "TIEN",
+        "ELF",
+        "TWAALF",
+        "DERTIEN",
+        "VEERTIEN",
+        "VIJFTIEN",
+        "ZESTIEN",
+        "ZEVENTIEN",
+        "ACHTTIEN",
+        "NEGENTIEN",
+        "TWINTIG",
+        "EENENTWINTIG",
+        "TWEEËNTWINTIG",
+        "DRIEËNTWINTIG",
+        "VIERENTWINTIG",
+        "VIJFENTWINTIG",
+        "ZESENTWINTIG",
+        "ZEVENENTWINTIG",
+        "ACHTENTWINTIG",
+        "NEGENENTWINTIG",
+    ];
+
+    solo[n as usize].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spell_times() {
+        let spell = |hours, minutes| spell(Time::new(hours, minutes), HourRollover::TwentyFour);
+
+        assert_eq!(spell(1, 0), "EEN UUR");
+        assert_eq!(spell(12, 0), "TWAALF UUR");
+        assert_eq!(spell(0, 0), "MIDDERNACHT");
+        assert_eq!(spell(14, 5), "VIJF OVER TWEE");
+        assert_eq!(spell(14, 15), "KWART OVER TWEE");
+        assert_eq!(spell(14, 20), "TIEN VOOR HALF DRIE");
+        assert_eq!(spell(14, 30), "HALF DRIE");
+        assert_eq!(spell(14, 35), "VIJF OVER HALF DRIE");
+        assert_eq!(spell(14, 45), "KWART VOOR DRIE");
+        assert_eq!(spell(23, 50), "TIEN VOOR MIDDERNACHT");
+        assert_eq!(spell(11, 38), "ACHT OVER HALF TWAALF");
+        assert_eq!(spell(4, 22), "ACHT VOOR HALF VIJF");
+        assert_eq!(spell(4, 38), "ACHT OVER HALF VIJF");
+    }
+
+    #[test]
+    fn all_times() {
+        for time in Time::all_times() {
+            let phrase = spell(time, HourRollover::TwentyFour);
+            assert!(phrase.chars().all(|c| c.is_ascii_uppercase() || c == ' '));
+        }
+    }
+}
@@ -1,33 +1,41 @@
+use crate::generate_phrases::HourRollover;
 use crate::models::time::Time;
 
-pub fn spell(time: Time) -> String {
+pub fn spell(time: Time, rollover: HourRollover) -> String {
     match (time.hours(), time.minutes()) {
-        (hours, 0) => spell_hours(hours, true),
-        (hours, 15) => format!("QUARTER PAST {}", spell_hours(hours, false)),
-        (hours, 30) => format!("HALF PAST {}", spell_hours(hours, false)),
-        (hours, 45) => format!("QUARTER TO {}", spell_hours((hours + 1) % 24, false)),
+        (hours, 0) => spell_hours(hours, true, rollover),
+        (hours, 15) => format!("QUARTER PAST {}", spell_hours(hours, false, rollover)),
+        (hours, 30) => format!("HALF PAST {}", spell_hours(hours, false, rollover)),
+        (hours, 45) => format!(
+            "QUARTER TO {}",
+            spell_hours(rollover.next_hour(hours), false, rollover)
+        ),
         (hours, minutes) if minutes < 30 => format!(
             "{} PAST {}",
             spell_number(minutes),
-            spell_hours(hours, false)
+            spell_hours(hours, false, rollover)
         ),
         (hours, minutes) => format!(
             "{} TO {}",
             spell_number(60 - minutes),
-            spell_hours((hours + 1) % 24, false),
+            spell_hours(rollover.next_hour(hours), false, rollover),
         ),
     }
 }
 
-fn spell_hours(n: u8, include_o_clock: bool) -> String {
+fn spell_hours(n: u8, include_o_clock: bool, rollover: HourRollover) -> String {
     assert!(n < 24);
 
     match (n, include_o_clock) {
+        (0 | 12, true) if rollover == HourRollover::Twelve => {
+            format!("{} O CLOCK", spell_number(12))
+        }
+        (0 | 12, false) if rollover == HourRollover::Twelve => spell_number(12),
         (0, _) => "MIDNIGHT".to_owned(),
         (12, _) => "MIDDAY".to_owned(),
         (n, true) if n < 12 => format!("{} O CLOCK", spell_number(n)),
         (n, false) if n < 12 => spell_number(n),
-        (n, include_o_clock) => spell_hours(n - 12, include_o_clock),
+        (n, include_o_clock) => spell_hours(n - 12, include_o_clock, rollover),
     }
 }
 
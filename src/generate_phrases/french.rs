@@ -1,36 +1,56 @@
+use crate::generate_phrases::HourRollover;
 use crate::models::time::Time;
 
-pub fn spell(time: Time) -> String {
+/// The regional variant of French, which only changes how the numbers from 70 to 99 are spelled
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dialect {
+    /// "SOIXANTE DIX", "QUATRE VINGTS" and "QUATRE VINGT DIX"
+    France,
+    /// "SEPTANTE", "HUITANTE" and "NONANTE"
+    Swiss,
+}
+
+pub fn spell(time: Time, rollover: HourRollover, dialect: Dialect) -> String {
     match (time.hours(), time.minutes()) {
-        (hours, 0) => spell_hours(hours),
-        (hours, 15) => format!("{} ET QUART", spell_hours(hours)),
-        (hours, 30) => format!("{} ET DEMIE", spell_hours(hours)),
-        (hours, 45) => format!("{} MOINS LE QUART", spell_hours((hours + 1) % 24)),
+        (hours, 0) => spell_hours(hours, dialect, rollover),
+        (hours, 15) => format!("{} ET QUART", spell_hours(hours, dialect, rollover)),
+        (hours, 30) => format!("{} ET DEMIE", spell_hours(hours, dialect, rollover)),
+        (hours, 45) => format!(
+            "{} MOINS LE QUART",
+            spell_hours(rollover.next_hour(hours), dialect, rollover)
+        ),
         (hours, minutes) if minutes < 30 => {
-            format!("{} {}", spell_hours(hours), spell_number(minutes, false))
+            format!(
+                "{} {}",
+                spell_hours(hours, dialect, rollover),
+                spell_number(minutes, false, dialect)
+            )
         }
         (hours, minutes) => format!(
             "{} MOINS {}",
-            spell_hours((hours + 1) % 24),
-            spell_number(60 - minutes, false),
+            spell_hours(rollover.next_hour(hours), dialect, rollover),
+            spell_number(60 - minutes, false, dialect),
         ),
     }
 }
 
-fn spell_hours(n: u8) -> String {
+fn spell_hours(n: u8, dialect: Dialect, rollover: HourRollover) -> String {
     assert!(n < 24);
 
     match n {
+        0 | 12 if rollover == HourRollover::Twelve => {
+            format!("{} HEURES", spell_number(12, false, dialect))
+        }
         0 => "MINUIT".to_owned(),
         1 => "UNE HEURE".to_owned(),
         12 => "MIDI".to_owned(),
-        n if n < 12 => format!("{} HEURES", spell_number(n, false)),
-        n => spell_hours(n - 12),
+        n if n < 12 => format!("{} HEURES", spell_number(n, false, dialect)),
+        n => spell_hours(n - 12, dialect, rollover),
     }
 }
 
-fn spell_number(n: u8, masculine: bool) -> String {
-    assert!(n < 60);
+fn spell_number(n: u8, masculine: bool, dialect: Dialect) -> String {
+    assert!(n < 100);
 
     let solo = &[
         "",
@@ -52,18 +72,62 @@ fn spell_number(n: u8, masculine: bool) -> String {
         "SEIZE",
     ];
 
-    let composed = &["DIX", "VINGT", "TRENTE", "QUARANTE", "CINQUANTE"];
+    let composed = &[
+        "DIX",
+        "VINGT",
+        "TRENTE",
+        "QUARANTE",
+        "CINQUANTE",
+        "SOIXANTE",
+        "SEPTANTE",
+        "HUITANTE",
+        "NONANTE",
+    ];
 
     match (n, masculine) {
         (1, true) => "UN".to_owned(),
         (1, false) => "UNE".to_owned(),
         (n, _) if n < 17 => solo[n as usize].to_owned(),
+        // In France, the numbers from 70 are counted in twenties: 71 is "60 + 11"
+        (71, _) if dialect == Dialect::France => "SOIXANTE ET ONZE".to_owned(),
+        (80, _) if dialect == Dialect::France => "QUATRE VINGTS".to_owned(),
+        (n, masculine) if n >= 70 && dialect == Dialect::France => {
+            let (prefix, rest) = if n < 80 {
+                ("SOIXANTE", n - 60)
+            } else {
+                ("QUATRE VINGT", n - 80)
+            };
+            format!("{} {}", prefix, spell_number(rest, masculine, dialect))
+        }
         (n, _) if n % 10 == 0 => composed[(n / 10 - 1) as usize].to_owned(),
         (n, masculine) => format!(
             "{}{}{}",
             composed[(n / 10 - 1) as usize],
             if n % 10 == 1 { " ET " } else { " " },
-            spell_number(n % 10, masculine)
+            spell_number(n % 10, masculine, dialect)
         ),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dialects() {
+        let france = |n| spell_number(n, true, Dialect::France);
+        let swiss = |n| spell_number(n, true, Dialect::Swiss);
+
+        assert_eq!(france(21), "VINGT ET UN");
+        assert_eq!(swiss(21), "VINGT ET UN");
+        assert_eq!(france(70), "SOIXANTE DIX");
+        assert_eq!(swiss(70), "SEPTANTE");
+        assert_eq!(france(71), "SOIXANTE ET ONZE");
+        assert_eq!(swiss(71), "SEPTANTE ET UN");
+        assert_eq!(france(80), "QUATRE VINGTS");
+        assert_eq!(swiss(80), "HUITANTE");
+        assert_eq!(france(81), "QUATRE VINGT UN");
+        assert_eq!(france(97), "QUATRE VINGT DIX SEPT");
+        assert_eq!(swiss(97), "NONANTE SEPT");
+    }
+}
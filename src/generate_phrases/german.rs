@@ -1,57 +1,163 @@
+use crate::generate_phrases::{normalize_letters, HourRollover};
 use crate::models::time::Time;
 
-pub fn spell(time: Time) -> String {
+/// How the minutes are counted
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    /// The minutes close to the half hour are relative to it, like "ZEHN VOR HALB SECHS"
+    HalfHour,
+    /// The minutes are counted past the hour or to the next one, like "ZWANZIG NACH FÜNF"
+    Minutes,
+}
+
+/// Spell the time in German. The words are written with their umlauts and "ß", which are then
+/// normalized to A-Z, like "ZWÖLF" to "ZWOLF".
+pub fn spell(time: Time, rollover: HourRollover, style: Style) -> String {
     let hours = time.hours();
     let minutes = time.minutes();
-    let next_hour = (hours + 1) % 24;
-    match minutes {
-        0 => spell_hours(hours, true),
-        1..=14 => format!(
+    let next_hour = rollover.next_hour(hours);
+    let phrase = match (style, minutes) {
+        (_, 0) => spell_hours(hours, true, rollover),
+        (_, 15) => format!("VIERTEL NACH {}", spell_hours(hours, false, rollover)),
+        (_, 30) => format!("HALB {}", spell_hours(next_hour, false, rollover)),
+        (_, 45) => format!("VIERTEL VOR {}", spell_hours(next_hour, false, rollover)),
+        (Style::Minutes, 1..=29) => format!(
             "{} NACH {}",
             spell_number(minutes),
-            spell_hours(hours, false)
+            spell_hours(hours, false, rollover)
+        ),
+        (Style::Minutes, 31..=59) => format!(
+            "{} VOR {}",
+            spell_number(60 - minutes),
+            spell_hours(next_hour, false, rollover)
         ),
-        15 => format!("VIERTEL NACH {}", spell_hours(hours, false)),
-        16..=29 => format!(
+        (Style::HalfHour, 1..=14) => format!(
+            "{} NACH {}",
+            spell_number(minutes),
+            spell_hours(hours, false, rollover)
+        ),
+        (Style::HalfHour, 16..=29) => format!(
             "{} VOR HALB {}",
             spell_number(30 - minutes),
-            spell_hours(next_hour, false)
+            spell_hours(next_hour, false, rollover)
         ),
-        30 => format!("HALB {}", spell_hours(next_hour, false)),
-        31..=44 => format!(
+        (Style::HalfHour, 31..=44) => format!(
             "{} NACH HALB {}",
             spell_number(45 - minutes),
-            spell_hours(next_hour, false)
+            spell_hours(next_hour, false, rollover)
         ),
-        45 => format!("VIERTEL VOR {}", spell_hours(next_hour, false)),
-        46..=59 => format!(
+        (Style::HalfHour, 46..=59) => format!(
             "{} VOR {}",
             spell_number(60 - minutes),
-            spell_hours(next_hour, false)
+            spell_hours(next_hour, false, rollover)
         ),
         _ => unreachable!(),
-    }
+    };
+
+    normalize_letters(&phrase)
 }
 
-fn spell_hours(n: u8, include_um: bool) -> String {
+fn spell_hours(n: u8, include_um: bool, rollover: HourRollover) -> String {
     assert!(n < 24);
 
     match (n, include_um) {
+        (0 | 12, true) if rollover == HourRollover::Twelve => format!("UM {}", spell_number(12)),
+        (0 | 12, false) if rollover == HourRollover::Twelve => spell_number(12),
         (0, _) => "MITTERNACHT".to_owned(),
         (12, _) => "MITTAG".to_owned(),
         (n, true) if n < 12 => format!("UM {}", spell_number(n)),
         (n, false) if n < 12 => spell_number(n),
-        (n, include_o_clock) => spell_hours(n - 12, include_o_clock),
+        (n, include_o_clock) => spell_hours(n - 12, include_o_clock, rollover),
     }
 }
 
 fn spell_number(n: u8) -> String {
-    assert!(n < 15);
+    assert!(n < 60);
 
     let solo = &[
-        "", "EINS", "ZWEI", "DREI", "VIER", "FUNF", "SECHS", "SIEBEN", "ACHT", "NEUN", "ZEHN",
-        "ELF", "ZWOLF", "DREIZEHN", "VIERZEHN",
+        "NULL",
+        "EINS",
+        "ZWEI",
+        "DREI",
+        "VIER",
+        "FÜNF",
+        "SECHS",
+        "SIEBEN",
+        "ACHT",
+        "NEUN",
+        "ZEHN",
+        "ELF",
+        "ZWÖLF",
+        "DREIZEHN",
+        "VIERZEHN",
+        "FÜNFZEHN",
+        "SECHZEHN",
+        "SIEBZEHN",
+        "ACHTZEHN",
+        "NEUNZEHN",
     ];
+    let tens = &["", "", "ZWANZIG", "DREIßIG", "VIERZIG", "FÜNFZIG"];
+
+    match (n / 10, n % 10) {
+        _ if n < 20 => solo[n as usize].to_owned(),
+        (t, 0) => tens[t as usize].to_owned(),
+        (t, 1) => format!("EINUND{}", tens[t as usize]),
+        (t, u) => format!("{}UND{}", solo[u as usize], tens[t as usize]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn half_hour_style() {
+        let spell = |hours, minutes| {
+            spell(
+                Time::new(hours, minutes),
+                HourRollover::TwentyFour,
+                Style::HalfHour,
+            )
+        };
+
+        assert_eq!(spell(3, 0), "UM DREI");
+        assert_eq!(spell(0, 0), "MITTERNACHT");
+        assert_eq!(spell(5, 10), "ZEHN NACH FUNF");
+        assert_eq!(spell(5, 15), "VIERTEL NACH FUNF");
+        assert_eq!(spell(5, 20), "ZEHN VOR HALB SECHS");
+        assert_eq!(spell(5, 30), "HALB SECHS");
+        assert_eq!(spell(11, 45), "VIERTEL VOR MITTAG");
+        assert_eq!(spell(23, 55), "FUNF VOR MITTERNACHT");
+    }
 
-    solo[n as usize].to_owned()
+    #[test]
+    fn minutes_style() {
+        let spell = |hours, minutes| {
+            spell(
+                Time::new(hours, minutes),
+                HourRollover::TwentyFour,
+                Style::Minutes,
+            )
+        };
+
+        assert_eq!(spell(3, 0), "UM DREI");
+        assert_eq!(spell(5, 15), "VIERTEL NACH FUNF");
+        assert_eq!(spell(5, 20), "ZWANZIG NACH FUNF");
+        assert_eq!(spell(5, 21), "EINUNDZWANZIG NACH FUNF");
+        assert_eq!(spell(5, 30), "HALB SECHS");
+        assert_eq!(spell(5, 35), "FUNFUNDZWANZIG VOR SECHS");
+        assert_eq!(spell(11, 45), "VIERTEL VOR MITTAG");
+    }
+
+    #[test]
+    fn numbers() {
+        let spell_number = |n| normalize_letters(&spell_number(n));
+
+        assert_eq!(spell_number(0), "NULL");
+        assert_eq!(spell_number(17), "SIEBZEHN");
+        assert_eq!(spell_number(30), "DREISSIG");
+        assert_eq!(spell_number(42), "ZWEIUNDVIERZIG");
+        assert_eq!(spell_number(51), "EINUNDFUNFZIG");
+        assert_eq!(spell_number(59), "NEUNUNDFUNFZIG");
+    }
 }
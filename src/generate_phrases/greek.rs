@@ -0,0 +1,105 @@
+use crate::generate_phrases::HourRollover;
+use crate::models::time::Time;
+
+/// Spell the time in Greek, transliterated to A-Z: "Η ΩΡΑ" becomes "I ORA", "ΧΤ" becomes "CHT",
+/// "Ξ" becomes "X", and so on
+pub fn spell(time: Time, rollover: HourRollover) -> String {
+    let hours = time.hours();
+    let minutes = time.minutes();
+    let next_hour = rollover.next_hour(hours);
+    match minutes {
+        0 => spell_hours(hours, true, rollover),
+        15 => format!("{} KAI TETARTO", spell_hours(hours, false, rollover)),
+        30 => format!("{} KAI MISI", spell_hours(hours, false, rollover)),
+        45 => format!("{} PARA TETARTO", spell_hours(next_hour, false, rollover)),
+        1..=29 => format!(
+            "{} KAI {}",
+            spell_hours(hours, false, rollover),
+            spell_number(minutes, false)
+        ),
+        31..=59 => format!(
+            "{} PARA {}",
+            spell_hours(next_hour, false, rollover),
+            spell_number(60 - minutes, false)
+        ),
+        _ => unreachable!(),
+    }
+}
+
+/// Spell the hour, which agrees with the feminine "ORA"
+fn spell_hours(n: u8, include_ora: bool, rollover: HourRollover) -> String {
+    assert!(n < 24);
+
+    match (n, include_ora) {
+        (0 | 12, true) if rollover == HourRollover::Twelve => {
+            format!("{} I ORA", spell_number(12, true))
+        }
+        (0 | 12, false) if rollover == HourRollover::Twelve => spell_number(12, true),
+        (0, _) => "MESANYCHTA".to_owned(),
+        (12, _) => "MESIMERI".to_owned(),
+        (n, true) if n < 12 => format!("{} I ORA", spell_number(n, true)),
+        (n, false) if n < 12 => spell_number(n, true),
+        (n, include_ora) => spell_hours(n - 12, include_ora, rollover),
+    }
+}
+
+/// Spell the number in its feminine form, for the hours, or in its neuter form, for the minutes.
+/// Only one, three and four, alone or as units, have different forms.
+fn spell_number(n: u8, feminine: bool) -> String {
+    assert!(n < 30);
+
+    let solo = &[
+        "",
+        "SPECIAL_CASE",
+        "DYO",
+        "SPECIAL_CASE",
+        "SPECIAL_CASE",
+        "PENTE",
+        "EXI",
+        "EFTA",
+        "OCHTO",
+        "ENNEA",
+        "DEKA",
+        "ENTEKA",
+        "DODEKA",
+    ];
+
+    match (n, feminine) {
+        (1, true) => "MIA".to_owned(),
+        (1, false) => "ENA".to_owned(),
+        (3, true) => "TREIS".to_owned(),
+        (3, false) => "TRIA".to_owned(),
+        (4, true) => "TESSERIS".to_owned(),
+        (4, false) => "TESSERA".to_owned(),
+        (n, _) if n <= 12 => solo[n as usize].to_owned(),
+        (n, feminine) if n < 20 => format!("DEKA{}", spell_number(n - 10, feminine)),
+        (20, _) => "EIKOSI".to_owned(),
+        (n, feminine) => format!("EIKOSI {}", spell_number(n - 20, feminine)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spell_times() {
+        let spell = |hours, minutes| spell(Time::new(hours, minutes), HourRollover::TwentyFour);
+
+        assert_eq!(spell(1, 0), "MIA I ORA");
+        assert_eq!(spell(2, 0), "DYO I ORA");
+        assert_eq!(spell(15, 0), "TREIS I ORA");
+        assert_eq!(spell(0, 0), "MESANYCHTA");
+        assert_eq!(spell(1, 15), "MIA KAI TETARTO");
+        assert_eq!(spell(4, 30), "TESSERIS KAI MISI");
+        assert_eq!(spell(3, 45), "TESSERIS PARA TETARTO");
+        assert_eq!(spell(11, 45), "MESIMERI PARA TETARTO");
+
+        // The minutes are neuter, while the hours are feminine
+        assert_eq!(spell(1, 1), "MIA KAI ENA");
+        assert_eq!(spell(3, 13), "TREIS KAI DEKATRIA");
+        assert_eq!(spell(13, 24), "MIA KAI EIKOSI TESSERA");
+        assert_eq!(spell(0, 56), "MIA PARA TESSERA");
+        assert_eq!(spell(8, 40), "ENNEA PARA EIKOSI");
+    }
+}
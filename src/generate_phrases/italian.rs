@@ -0,0 +1,114 @@
+use crate::generate_phrases::{normalize_letters, HourRollover};
+use crate::models::time::Time;
+
+/// Spell the time in Italian. The accents are dropped and the apostrophes separate words, so that
+/// "È L'UNA" is spelled "E L UNA".
+pub fn spell(time: Time, rollover: HourRollover) -> String {
+    let phrase = match (time.hours(), time.minutes()) {
+        (hours, 0) => spell_hours(hours, rollover),
+        (hours, 15) => format!("{} E UN QUARTO", spell_hours(hours, rollover)),
+        (hours, 30) => format!("{} E MEZZA", spell_hours(hours, rollover)),
+        (hours, 45) => format!(
+            "{} MENO UN QUARTO",
+            spell_hours(rollover.next_hour(hours), rollover)
+        ),
+        (hours, minutes) if minutes < 30 => {
+            format!(
+                "{} E {}",
+                spell_hours(hours, rollover),
+                spell_number(minutes)
+            )
+        }
+        (hours, minutes) => format!(
+            "{} MENO {}",
+            spell_hours(rollover.next_hour(hours), rollover),
+            spell_number(60 - minutes)
+        ),
+    };
+
+    normalize_letters(&phrase)
+}
+
+/// Spell the hour with its verb and article, which are singular only for one o'clock: "È L'UNA",
+/// but "SONO LE DUE"
+fn spell_hours(hours: u8, rollover: HourRollover) -> String {
+    assert!(hours < 24);
+
+    match hours {
+        0 | 12 if rollover == HourRollover::Twelve => format!("SONO LE {}", spell_number(12)),
+        0 => "È MEZZANOTTE".to_owned(),
+        1 => "È L'UNA".to_owned(),
+        12 => "È MEZZOGIORNO".to_owned(),
+        n if n < 12 => format!("SONO LE {}", spell_number(n)),
+        n => spell_hours(n - 12, rollover),
+    }
+}
+
+fn spell_number(n: u8) -> String {
+    assert!(n < 30);
+
+    let solo = &[
+        "",
+        "UNO",
+        "DUE",
+        "TRE",
+        "QUATTRO",
+        "CINQUE",
+        "SEI",
+        "SETTE",
+        "OTTO",
+        "NOVE",
+        "DIECI",
+        "UNDICI",
+        "DODICI",
+        "TREDICI",
+        "QUATTORDICI",
+        "QUINDICI",
+        "SEDICI",
+        "DICIASSETTE",
+        "DICIOTTO",
+        "DICIANNOVE",
+        "VENTI",
+        "VENTUNO",
+        "VENTIDUE",
+        "VENTITRÉ",
+        "VENTIQUATTRO",
+        "VENTICINQUE",
+        "VENTISEI",
+        "VENTISETTE",
+        "VENTOTTO",
+        "VENTINOVE",
+    ];
+
+    solo[n as usize].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::text::Text;
+
+    #[test]
+    fn spell_times() {
+        let spell = |hours, minutes| spell(Time::new(hours, minutes), HourRollover::TwentyFour);
+
+        assert_eq!(spell(1, 0), "E L UNA");
+        assert_eq!(spell(2, 0), "SONO LE DUE");
+        assert_eq!(spell(0, 0), "E MEZZANOTTE");
+        assert_eq!(spell(12, 0), "E MEZZOGIORNO");
+        assert_eq!(spell(13, 15), "E L UNA E UN QUARTO");
+        assert_eq!(spell(4, 30), "SONO LE QUATTRO E MEZZA");
+        assert_eq!(spell(0, 45), "E L UNA MENO UN QUARTO");
+        assert_eq!(spell(7, 23), "SONO LE SETTE E VENTITRE");
+        assert_eq!(spell(9, 50), "SONO LE DIECI MENO DIECI");
+    }
+
+    #[test]
+    fn all_times_are_texts() {
+        for time in Time::all_times() {
+            for word in spell(time, HourRollover::TwentyFour).split(' ') {
+                assert!(word.parse::<Text>().is_ok(), "{} in {}", word, time);
+            }
+        }
+    }
+}
@@ -1,41 +1,66 @@
+use crate::generate_phrases::HourRollover;
 use crate::models::time::Time;
 
-pub fn spell(time: Time) -> String {
+pub fn spell(time: Time, rollover: HourRollover, period_suffix: bool) -> String {
+    let phrase = spell_time(time, rollover);
+    if period_suffix {
+        format!("{} {}", phrase, spell_period(time.hours()))
+    } else {
+        phrase
+    }
+}
+
+fn spell_time(time: Time, rollover: HourRollover) -> String {
     match (time.hours(), time.minutes()) {
-        (hours, 0) => spell_hours(hours),
-        (hours, 30) => format!("{} E MEIA", spell_hours(hours)),
+        (hours, 0) => spell_hours(hours, rollover),
+        (hours, 30) => format!("{} E MEIA", spell_hours(hours, rollover)),
         (hours, minutes) if minutes < 30 => {
-            format!("{} E {}", spell_hours(hours), spell_number(minutes, true))
+            format!(
+                "{} E {}",
+                spell_hours(hours, rollover),
+                spell_number(minutes, true)
+            )
         }
         (hours, minutes) => format!(
             "{} PARA {}",
             spell_number(60 - minutes, true),
-            spell_hours_with_article((hours + 1) % 24)
+            spell_hours_with_article(rollover.next_hour(hours), rollover)
         ),
     }
 }
 
-fn spell_hours(hours: u8) -> String {
+/// Return the period of the day, like "DA TARDE", transliterating "MANHÃ" as "MANHA"
+fn spell_period(hours: u8) -> &'static str {
+    match hours {
+        h if h < 12 => "DA MANHA",
+        h if h < 18 => "DA TARDE",
+        _ => "DA NOITE",
+    }
+}
+
+fn spell_hours(hours: u8, rollover: HourRollover) -> String {
     assert!(hours < 24);
 
     match hours {
+        0 | 12 if rollover == HourRollover::Twelve => format!("{} HORAS", spell_number(12, false)),
         0 => "MEIA NOITE".to_owned(),
         1 => "UMA HORA".to_owned(),
         12 => "MEIO DIA".to_owned(),
         n if n < 12 => format!("{} HORAS", spell_number(n, false)),
-        n => spell_hours(n - 12),
+        n => spell_hours(n - 12, rollover),
     }
 }
 
-fn spell_hours_with_article(hours: u8) -> String {
+fn spell_hours_with_article(hours: u8, rollover: HourRollover) -> String {
     assert!(hours < 24);
 
     match hours {
+        0 | 12 if rollover == HourRollover::Twelve => format!("AS {}", spell_number(12, false)),
         0 => "A MEIA NOITE".to_owned(),
         1 => "A UMA".to_owned(),
         12 => "O MEIO DIA".to_owned(),
         n if n < 12 => format!("AS {}", spell_number(n, false)),
-        n => spell_hours_with_article(n - 12),
+        n => spell_hours_with_article(n - 12, rollover),
     }
 }
 
@@ -81,3 +106,16 @@ fn spell_number(n: u8, masculine: bool) -> String {
         ),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn period_suffix() {
+        let spell = |hours| spell(Time::new(hours, 0), HourRollover::TwentyFour, true);
+        assert_eq!(spell(9), "NOVE HORAS DA MANHA");
+        assert_eq!(spell(15), "TRES HORAS DA TARDE");
+        assert_eq!(spell(21), "NOVE HORAS DA NOITE");
+    }
+}
@@ -0,0 +1,115 @@
+use crate::generate_phrases::{normalize_letters, HourRollover};
+use crate::models::time::Time;
+
+/// Spell the time in Spanish. The accents are dropped, so that "ES MEDIODÍA" is spelled
+/// "ES MEDIODIA".
+pub fn spell(time: Time, rollover: HourRollover) -> String {
+    let phrase = match (time.hours(), time.minutes()) {
+        (hours, 0) => spell_hours(hours, rollover),
+        (hours, 15) => format!("{} Y CUARTO", spell_hours(hours, rollover)),
+        (hours, 30) => format!("{} Y MEDIA", spell_hours(hours, rollover)),
+        (hours, 45) => format!(
+            "{} MENOS CUARTO",
+            spell_hours(rollover.next_hour(hours), rollover)
+        ),
+        (hours, minutes) if minutes < 30 => {
+            format!(
+                "{} Y {}",
+                spell_hours(hours, rollover),
+                spell_number(minutes)
+            )
+        }
+        (hours, minutes) => format!(
+            "{} MENOS {}",
+            spell_hours(rollover.next_hour(hours), rollover),
+            spell_number(60 - minutes)
+        ),
+    };
+
+    normalize_letters(&phrase)
+}
+
+/// Spell the hour with its verb and article, which are singular only for one o'clock: "ES LA UNA",
+/// but "SON LAS DOS"
+fn spell_hours(hours: u8, rollover: HourRollover) -> String {
+    assert!(hours < 24);
+
+    match hours {
+        0 | 12 if rollover == HourRollover::Twelve => format!("SON LAS {}", spell_number(12)),
+        0 => "ES MEDIANOCHE".to_owned(),
+        1 => "ES LA UNA".to_owned(),
+        12 => "ES MEDIODÍA".to_owned(),
+        n if n < 12 => format!("SON LAS {}", spell_number(n)),
+        n => spell_hours(n - 12, rollover),
+    }
+}
+
+fn spell_number(n: u8) -> String {
+    assert!(n < 30);
+
+    let solo = &[
+        "",
+        "UNO",
+        "DOS",
+        "TRES",
+        "CUATRO",
+        "CINCO",
+        "SEIS",
+        "SIETE",
+        "OCHO",
+        "NUEVE",
+        "DIEZ",
+        "ONCE",
+        "DOCE",
+        "TRECE",
+        "CATORCE",
+        "QUINCE",
+        "DIECISÉIS",
+        "DIECISIETE",
+        "DIECIOCHO",
+        "DIECINUEVE",
+        "VEINTE",
+        "VEINTIUNO",
+        "VEINTIDÓS",
+        "VEINTITRÉS",
+        "VEINTICUATRO",
+        "VEINTICINCO",
+        "VEINTISÉIS",
+        "VEINTISIETE",
+        "VEINTIOCHO",
+        "VEINTINUEVE",
+    ];
+
+    solo[n as usize].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::text::Text;
+
+    #[test]
+    fn spell_times() {
+        let spell = |hours, minutes| spell(Time::new(hours, minutes), HourRollover::TwentyFour);
+
+        assert_eq!(spell(1, 0), "ES LA UNA");
+        assert_eq!(spell(2, 0), "SON LAS DOS");
+        assert_eq!(spell(0, 0), "ES MEDIANOCHE");
+        assert_eq!(spell(12, 0), "ES MEDIODIA");
+        assert_eq!(spell(13, 15), "ES LA UNA Y CUARTO");
+        assert_eq!(spell(4, 30), "SON LAS CUATRO Y MEDIA");
+        assert_eq!(spell(0, 45), "ES LA UNA MENOS CUARTO");
+        assert_eq!(spell(23, 45), "ES MEDIANOCHE MENOS CUARTO");
+        assert_eq!(spell(7, 22), "SON LAS SIETE Y VEINTIDOS");
+        assert_eq!(spell(9, 50), "SON LAS DIEZ MENOS DIEZ");
+    }
+
+    #[test]
+    fn all_times_are_texts() {
+        for time in Time::all_times() {
+            for word in spell(time, HourRollover::TwentyFour).split(' ') {
+                assert!(word.parse::<Text>().is_ok(), "{} in {}", word, time);
+            }
+        }
+    }
+}
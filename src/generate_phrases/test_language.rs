@@ -0,0 +1,25 @@
+use crate::generate_phrases::HourRollover;
+use crate::models::time::Time;
+
+/// A synthetic language with regular phrases, like "HOUR ONE TWO MINUTE THREE ZERO", to test the
+/// pipeline without the quirks of natural languages
+pub fn spell(time: Time, _rollover: HourRollover) -> String {
+    format!(
+        "HOUR {} MINUTE {}",
+        spell_number(time.hours()),
+        spell_number(time.minutes())
+    )
+}
+
+/// Spell each digit of the number
+fn spell_number(n: u8) -> String {
+    let digits = &[
+        "ZERO", "ONE", "TWO", "THREE", "FOUR", "FIVE", "SIX", "SEVEN", "EIGHT", "NINE",
+    ];
+
+    n.to_string()
+        .chars()
+        .map(|c| digits[c.to_digit(10).expect("Must be a digit") as usize])
+        .collect::<Vec<_>>()
+        .join(" ")
+}
@@ -0,0 +1,5 @@
+pub mod build_grid;
+pub mod compile_lyrics_page;
+pub mod generate_phrases;
+pub mod models;
+pub mod tokenize;
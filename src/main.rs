@@ -1,32 +1,44 @@
+use std::convert::TryFrom;
 use std::env::VarError;
-use std::path::PathBuf;
-use std::time::Instant;
-use std::{env, fs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use std::{env, fs, thread};
 
-use crate::build_grid::compile_html::compile_html;
-use crate::compile_lyrics_page::AnimationConfig;
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
+use itertools::Itertools;
 use jemallocator::Jemalloc;
+use mhorloge::build_grid::compile_html::{compile_html, GlyphMap};
+use mhorloge::build_grid::{BuildGridConfig, InsertOrder, Warning};
+use mhorloge::compile_lyrics_page::{AnimationConfig, LyricsFormat, RevealDirection, TimingMode};
+use mhorloge::generate_phrases::{HourRollover, LanguageSpec};
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
 use structopt::StructOpt;
 
-use crate::models::aspect_ratio::AspectRatio;
-use crate::models::grid::Grid;
-use crate::models::io::{
-    GridInput, GridOutput, GridOutputPhrase, GridOutputWord, LyricsPuzzleInput, TimePhrasesOutput,
+use mhorloge::models::aspect_ratio::AspectRatio;
+use mhorloge::models::explanation::Explanation;
+use mhorloge::models::grid::{FillBias, FillConfig, FrameMode, Grid, InsertionConfig};
+use mhorloge::models::grid_bag::GridWeight;
+use mhorloge::models::io::{
+    CoordOrder, GridInput, GridOutput, GridOutputPhrase, GridOutputWord, InputType,
+    LyricsPuzzleInput, PhraseOrder, PhrasesFormat, TimePhrasesOutput, GRID_OUTPUT_VERSION,
 };
-use crate::models::language::Language;
-use crate::models::merge_dag::MergeDag;
-use crate::models::phrase::Phrase;
-use crate::models::phrase_book::PhraseBook;
-use crate::models::positioned_token::XY;
-use crate::models::token::Token;
-use crate::models::word::WordId;
-
-mod build_grid;
-mod compile_lyrics_page;
-mod generate_phrases;
-mod models;
-mod tokenize;
+use mhorloge::models::language::Language;
+use mhorloge::models::letter::Letter;
+use mhorloge::models::merge_dag::MergeDag;
+use mhorloge::models::phrase::Phrase;
+use mhorloge::models::phrase_book::PhraseBook;
+use mhorloge::models::position_restriction::ReadingAxis;
+use mhorloge::models::positioned_token::XY;
+use mhorloge::models::text::Text;
+use mhorloge::models::time::{Rounding, RoundingMode, Time, TimeOffset, TimeRange};
+use mhorloge::models::token::Token;
+use mhorloge::models::word::WordId;
+use mhorloge::tokenize::{
+    auto_chain_growth_head_space, phrase_tokens, tokenize, tokenize_explained, MergeObjective,
+};
+use regex::Regex;
 
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
@@ -36,34 +48,120 @@ static GLOBAL: Jemalloc = Jemalloc;
     name = "mhorloge",
     about = "CLI for problems related to the mhorloge project."
 )]
+struct Cli {
+    /// Fail when the command takes longer than this number of seconds, covering all its steps.
+    /// The outputs that were not written before the deadline are not written at all.
+    #[structopt(long)]
+    max_runtime: Option<u64>,
+    #[structopt(subcommand)]
+    command: Options,
+}
+
+#[derive(Debug, StructOpt)]
+// Parsed once, so the size of the largest subcommand does not matter
+#[allow(clippy::large_enum_variant)]
 enum Options {
     /// Generate time phrases and save them into a file
     TimePhrases {
-        /// Determine the languages to use. Available languages are: "English", "French", "Portuguese"
-        /// and "German". Multiple languages can be requested by separating them by comma. By
-        /// default, all time phrases will be generated, that is, from 00:00 to 12:00 with 1-minute
-        /// precision. To change the precision, append ":" followed by an integer representing the
-        /// desired precision after each language name. Each language can determine their own
-        /// precision.
+        /// Determine the languages to use. Available languages are: "English", "French",
+        /// "FrenchSwiss", "Portuguese", "German", "GermanMinutes", counting the minutes past the
+        /// hour, "Greek", transliterated to Latin letters, "Spanish", "Italian" and "Dutch".
+        /// Multiple languages can be requested by separating them by comma. By default, all time
+        /// phrases will be generated, that is, from 00:00 to 12:00 with 1-minute precision. To
+        /// change the precision, append ":" followed by an integer representing the desired
+        /// precision after each language name. Each language can determine their own precision.
+        /// Precisions that do not divide an hour evenly, that is, other than 1, 2, 3, 4, 5, 6, 10,
+        /// 12, 15, 20, 30 and 60, produce a warning.
+        ///
+        /// Portuguese also accepts the "+period" option, which adds the period of the day, like
+        /// "DA TARDE", after each phrase. For example: "Portuguese+period:5".
         ///
         /// Full example: "English:5,French" will generate for both languages, using a 1-minute
         /// precision for French and 5-minute precision for English.
         languages: String,
         /// The path to a file where to write the output as JSON, represented by `TimePhrasesOutput`.
         phrases_output: PathBuf,
+        /// When given, split the phrases across multiple files with at most this number of phrases
+        /// each and roughly the same size. The files are named after `phrases_output`, with the
+        /// index of the file before the extension: "output.0.json", "output.1.json", etc.
+        /// Together, they contain the same phrases as the single file output, in the same order.
+        #[structopt(long)]
+        phrases_per_file: Option<usize>,
+        /// How phrases that refer to the next hour, like "QUARTER TO ONE", compute it: "24" means
+        /// that 11:45 refers to midday and 23:45 to midnight. "12" means that both refer to the
+        /// hour 0 and that midday and midnight are both spelled as a neutral twelve, like "TWELVE O
+        /// CLOCK", which fits 12-hour dials.
+        #[structopt(long, default_value = "24")]
+        hour_rollover: HourRollover,
+        /// Words to add before every phrase, separated by spaces. For example: "IT IS".
+        #[structopt(long)]
+        prefix: Option<String>,
+        /// Words to add after every phrase, separated by spaces.
+        #[structopt(long)]
+        suffix: Option<String>,
+        /// The output format: "json" or "tsv", with one phrase per line and tab-separated
+        /// language, hours, minutes and phrase.
+        #[structopt(long, default_value = "json")]
+        format: PhrasesFormat,
+        /// Fail, instead of warning, when a precision does not divide an hour evenly.
+        #[structopt(long)]
+        strict_precision: bool,
+        /// Shift every time by this offset, like "+05:30" or "-01:00", before spelling it. The
+        /// phrases keep their original labels, so the phrase labeled 20:00 spells 01:30 with a
+        /// "+05:30" offset.
+        #[structopt(long, default_value = "+00:00")]
+        offset: TimeOffset,
+        /// Keep only the phrases that match this regular expression, like "QUARTER". The text of
+        /// each phrase is matched in uppercase, with its words joined by single spaces, including
+        /// the prefix and suffix.
+        #[structopt(long)]
+        filter: Option<Regex>,
+        /// Remove the phrases that spell the same words as a previous phrase of the same
+        /// language, keeping the first one. For example, 01:00 and 13:00 are both "ONE O CLOCK"
+        /// in English.
+        #[structopt(long)]
+        unique: bool,
+        /// Only generate the phrases from this time, like "09:00". The times are still stepped
+        /// from 00:00 by each language's precision.
+        #[structopt(long, default_value = "00:00")]
+        start: Time,
+        /// Only generate the phrases up to this time, inclusive, like "17:00". It must not be
+        /// before `--start`, since ranges that wrap around midnight are not supported.
+        #[structopt(long, default_value = "23:59")]
+        end: Time,
+        /// Round the minutes of each time to a multiple of this number before spelling it, like
+        /// classic word clocks that only show every 5 minutes. Consecutive times that spell the
+        /// same phrase are merged into the first one.
+        #[structopt(long)]
+        round_to: Option<u8>,
+        /// How `--round-to` rounds the minutes: "down" or "nearest".
+        #[structopt(long, default_value = "down")]
+        rounding_mode: RoundingMode,
     },
+    /// List the languages available to generate time phrases, with an example of their spec
+    Languages,
     /// Generate a grid for a given set of phrases
     Grid {
         /// The path to the input JSON file, represented by `GridInput`.
         phrases_input: PathBuf,
         /// The path to a file where to write the output as JSON, represented by `GridOutput`.
+        ///
+        /// When `--minimal-output` is given, this is represented by `FilledGridOutput` instead.
         grid_output: PathBuf,
         /// The path to a file where to write the output as HTML.
         #[structopt(long)]
         grid_html_output: Option<PathBuf>,
+        /// The path to a JSON file, represented by `GlyphMap`, with what the HTML output displays
+        /// for each letter, separately for the letters of the phrases and the random ones.
+        #[structopt(long)]
+        glyph_map: Option<PathBuf>,
         /// If present, will also try to position the token diagonally.
         #[structopt(long)]
         allow_diagonal: bool,
+        /// How the phrases are read: "row-major" means that each word comes after the previous
+        /// one in the same row or in a row below. "column-major" means the same, but with columns.
+        #[structopt(long, default_value = "row-major")]
+        reading_axis: ReadingAxis,
         /// The target aspect ratio, expressed by two integers separated by a colon ":".
         #[structopt(long, default_value = "32:9")]
         aspect_ratio: AspectRatio,
@@ -77,6 +175,14 @@ enum Options {
         /// You can install it with the `graphviz` package.
         #[structopt(long)]
         debug_tokens_svg: Option<PathBuf>,
+        /// Save the tokens graph in the GraphML format, to be analyzed by other tools.
+        #[structopt(long)]
+        tokens_graphml: Option<PathBuf>,
+        /// Save the ordering edges of the tokens graph as CSV, with the columns "from_id",
+        /// "from_text", "to_id" and "to_text". The tokens, with the columns "id", "text" and
+        /// "letters", are saved next to it, with "nodes" before the extension: "tokens.nodes.csv".
+        #[structopt(long)]
+        tokens_csv: Option<PathBuf>,
         /// When merging repeated words from different phrases together - into what's internally
         /// called tokens - they create chains that can be bigger than the original phrase.
         ///
@@ -84,6 +190,182 @@ enum Options {
         /// the longest original phrase.
         #[structopt(long, default_value = "1")]
         chain_growth_head_space: i32,
+        /// Instead of using `chain_growth_head_space`, try the values from 0 to 3 and pick the one
+        /// that minimizes the longest chain of tokens times the number of tokens.
+        #[structopt(long)]
+        auto_chain_growth: bool,
+        /// Which merges of repeated words into tokens are accepted: "fewest-tokens" accepts them
+        /// even if they grow the chains up to the maximum size, "shortest-chains" rejects any
+        /// growth and "balanced" accepts growing a chain by one word at a time.
+        #[structopt(long, default_value = "fewest-tokens")]
+        merge_objective: MergeObjective,
+        /// The order of the phrases in the output: "input" keeps the input order and "position"
+        /// sorts them by their top-most, then left-most, letter.
+        ///
+        /// Note that the lyrics puzzle pairs the lyrics phrases with the grid phrases by their
+        /// index, so it requires the "input" order.
+        #[structopt(long, default_value = "input")]
+        sort_phrases: PhraseOrder,
+        /// The maximum width and height of the minimal grid. When no grid can respect it, it will
+        /// be progressively relaxed.
+        #[structopt(long)]
+        max_grid_dimension: Option<i16>,
+        /// When inserting a token, each of its letters can be placed over any matching letter
+        /// already in the grid. This limits how many of those are tried, by randomly sampling
+        /// them. This trades completeness for speed on dense grids.
+        #[structopt(long)]
+        max_pivots_per_letter: Option<usize>,
+        /// Keep the phrases apart: a word cannot touch a word of an unrelated phrase, even
+        /// diagonally, unless they cross and share a letter.
+        #[structopt(long)]
+        phrase_separation: bool,
+        /// A JSON file with groups of synonyms, like `[["COLOR", "COLOUR"]]`. The words of each
+        /// group are placed as the first one, so that they share the same letters in the grid.
+        /// The HTML output still shows the phrases as given.
+        #[structopt(long)]
+        synonyms: Option<PathBuf>,
+        /// Forbid a word from starting right next to the end of the previous word of its phrase
+        /// when they have the same letter at that boundary, like "ONE" and "EIGHT", since the
+        /// letter could be read as shared by both.
+        #[structopt(long)]
+        separate_shared_boundaries: bool,
+        /// The number of rows and columns reserved around the content, for example for a
+        /// decorative border.
+        #[structopt(long, default_value = "0")]
+        frame: i16,
+        /// How the frame interacts with the aspect ratio: "inset" keeps it inside the aspect
+        /// ratio box and "outside" adds it around.
+        #[structopt(long, default_value = "outside")]
+        frame_mode: FrameMode,
+        /// The letter used to fill the frame. When absent, random letters are used, like the
+        /// rest of the grid. It is also the placeholder of the forbidden cells of the input in the
+        /// filled grid, which are empty in the minimal grid.
+        #[structopt(long)]
+        frame_letter: Option<char>,
+        /// The maximum fraction of the cells of the filled grid that can be added to honor the
+        /// aspect ratio, like "0.5". When more would be needed, the grid keeps the natural size of
+        /// the minimal grid instead.
+        #[structopt(long)]
+        max_aspect_padding: Option<f64>,
+        /// The maximum number of identical letters in a horizontal or vertical run that the
+        /// random fill can create, at least 1. The letters of the phrases are never altered. When
+        /// no allowed letter can avoid a longer run, one is used anyway and a warning is logged.
+        #[structopt(long)]
+        max_letter_run: Option<usize>,
+        /// How the random fill relates to the letters of the words nearby: "neutral" ignores
+        /// them, "blend" favors them to hide the words and "contrast" avoids them.
+        #[structopt(long, default_value = "neutral")]
+        fill_bias: FillBias,
+        /// Use at most this many distinct letters, randomly chosen, for the random fill.
+        #[structopt(long)]
+        fill_distinct_cap: Option<usize>,
+        /// When given, write the minimal grid and the phrase positions to this file, represented
+        /// by `MinimalGridOutput`, separately from the random fill. This allows keeping track of
+        /// the layout without the noise of the random letters.
+        #[structopt(long)]
+        minimal_output: Option<PathBuf>,
+        /// When given, write metrics of the grid to this file, represented by `GridStats`: its
+        /// size, the number of letters, crossings, decoys and connected components, the fraction
+        /// of diagonal words and the packing density.
+        #[structopt(long)]
+        grid_stats: Option<PathBuf>,
+        /// The index of the phrase whose first word should be in the top-left corner, like "IT
+        /// IS". This does not change the grid: it only checks where that word was placed and
+        /// warns when it is not at the top-left corner, telling how far from it the word is.
+        #[structopt(long)]
+        origin_phrase: Option<usize>,
+        /// How much to favor grids in which the words that appear together in phrases are close
+        /// to each other. This only breaks ties between grids with the same aspect ratio area and
+        /// number of letters.
+        #[structopt(long, default_value = "0")]
+        proximity_weight: f64,
+        /// How much to penalize grids in which consecutive words of a phrase change direction,
+        /// like a horizontal word followed by a vertical one. This only breaks ties, like
+        /// `proximity_weight`, so turns are still allowed.
+        #[structopt(long, default_value = "0")]
+        direction_change_penalty: f64,
+        /// How the candidate grids are ranked: "area" prefers the smallest area once covered by
+        /// the aspect ratio, then the fewest letters. "min-ink" prefers the fewest random letters
+        /// to cover the aspect ratio, for displays where every lit cell matters.
+        #[structopt(long, default_value = "area")]
+        grid_weight: GridWeight,
+        /// Build an independent grid for each language of the phrases, which requires the input
+        /// to come from the `time-phrases` command. The outputs are written for each language,
+        /// with its name before the extension: "grid.English.json", "grid.French.json", etc.
+        #[structopt(long)]
+        per_language: bool,
+        /// The order of the letter coordinates in the output phrases: "xy" for `(x, y)` or
+        /// "rowcol" for `(row, column)`. The origin is the top-left corner. The other commands,
+        /// like `refill` and `lyrics-puzzle`, only read the default "xy".
+        #[structopt(long, default_value = "xy")]
+        coord_order: CoordOrder,
+        /// The order in which the words are inserted into the grid: "depth" starts from the words
+        /// at the start and end of the phrases, "hardest" starts from the words that are the
+        /// hardest to place, considering their length, ordering constraints and rare letters.
+        #[structopt(long, default_value = "depth")]
+        insert_order: InsertOrder,
+        /// Fail before building the grid if there are more than this many tokens, since the
+        /// memory used grows with the square of their number. The number of tokens is logged.
+        #[structopt(long)]
+        max_tokens: Option<usize>,
+        /// When the input has more than this many phrases, keep only this many, randomly chosen
+        /// according to the seed, to build a best-effort grid. The dropped phrases are logged.
+        #[structopt(long)]
+        max_phrases: Option<usize>,
+        /// How many threads build the grid. By default, all cores are used. A single thread helps
+        /// profiling.
+        #[structopt(long)]
+        threads: Option<usize>,
+        /// The seed of the random choices, including the random letters. When absent, one is
+        /// derived from the contents of the phrases, so that the same input always gives the same
+        /// grid.
+        #[structopt(long)]
+        seed: Option<u64>,
+        /// Save all the grids that were considered at the last step, from the best to the worst,
+        /// with the components used to rank them, as a JSON array of `GridBagEntry`.
+        #[structopt(long)]
+        dump_bag: Option<PathBuf>,
+        /// Show a progress bar with the insertions done, the number of grids kept and the size of
+        /// the best one, instead of logging each insertion. Requires building with the "progress"
+        /// feature.
+        #[structopt(long)]
+        progress: bool,
+        /// Write a JSON record, represented by `Explanation`, of the repeated sequences, each
+        /// merge accepted or rejected with its chain sizes, the order in which the tokens were
+        /// inserted and where each token of the chosen grid was placed, with the tokens it
+        /// crosses.
+        #[structopt(long)]
+        explain: Option<PathBuf>,
+    },
+    /// Re-roll the random letters of a grid, keeping its layout and its fixed cells, like a frame
+    /// of placeholders
+    Refill {
+        /// The path to the grid JSON file, represented by `GridOutput`. The letter positions must
+        /// be in the default "xy" coordinate order.
+        grid_input: PathBuf,
+        /// The path to a file where to write the output as JSON, represented by `GridOutput`.
+        grid_output: PathBuf,
+        /// The seed of the random letters. When absent, a random one is used.
+        #[structopt(long)]
+        seed: Option<u64>,
+        /// The maximum number of identical letters in a horizontal or vertical run that the
+        /// random fill can create, at least 1. When no allowed letter can avoid a longer run, one
+        /// is used anyway and a warning is logged.
+        #[structopt(long)]
+        max_letter_run: Option<usize>,
+        /// How the random fill relates to the letters of the words nearby: "neutral", "blend" or
+        /// "contrast".
+        #[structopt(long, default_value = "neutral")]
+        fill_bias: FillBias,
+        /// Use at most this many distinct letters, randomly chosen, for the random fill.
+        #[structopt(long)]
+        fill_distinct_cap: Option<usize>,
+    },
+    /// Print a minimal valid example of an input JSON file
+    Example {
+        /// The input to exemplify: "grid" for `GridInput` or "lyrics-puzzle" for
+        /// `LyricsPuzzleInput`.
+        input_type: InputType,
     },
     /// Generate a HTML file to sync each letter of a grid with a song's lyrics
     LyricsPuzzle {
@@ -91,8 +373,19 @@ enum Options {
         lyrics_input: PathBuf,
         /// The path to the grid input JSON file, represented by `GridOutput`.
         grid_input: PathBuf,
-        /// The path to a file where to write the output as HTML.
-        html_output: PathBuf,
+        /// When the grid was produced with `--minimal-output`, the path to that file. The grid
+        /// input is then represented by `FilledGridOutput`.
+        #[structopt(long)]
+        minimal_input: Option<PathBuf>,
+        /// The path to a file where to write the output, in the format given by `--format`.
+        output: PathBuf,
+        /// The output format: "html" or "vtt". A WebVTT file has one cue per phrase and does not
+        /// animate the grid.
+        #[structopt(long, default_value = "html")]
+        format: LyricsFormat,
+        /// With `--format vtt`, also list the grid cells of the letters of each cue.
+        #[structopt(long)]
+        vtt_letters: bool,
         #[structopt(long, default_value = "250")]
         ease_in: i32,
         #[structopt(long, default_value = "0")]
@@ -103,8 +396,26 @@ enum Options {
         ease_out: i32,
         #[structopt(long, default_value = "42")]
         discrete_time_step: i32,
+        /// The ratio of each phrase duration dedicated to reveal its letters as an incoming wave.
         #[structopt(long, default_value = "0.5")]
         letters_entering: f64,
+        /// When given, reveal the letters at this fixed rate instead of using `letters_entering`.
+        /// The reveal is still limited by each phrase duration.
+        #[structopt(long)]
+        letters_per_second: Option<f64>,
+        /// The order in which the letters of each phrase are revealed: "reading" follows the
+        /// words, "grid-position" goes from top to bottom, then left to right, and "radial:X,Y"
+        /// starts from the letters closest to the cell (X, Y).
+        #[structopt(long, default_value = "reading")]
+        reveal_direction: RevealDirection,
+        /// The path to a JSON file, represented by `GlyphMap`, with what is displayed for each
+        /// letter, separately for the letters of the phrases and the random ones.
+        #[structopt(long)]
+        glyph_map: Option<PathBuf>,
+        /// Only check that the lyrics and the grid are consistent, reporting every problem, and
+        /// do not write the output.
+        #[structopt(long)]
+        validate_only: bool,
     },
 }
 
@@ -117,59 +428,263 @@ fn main() -> Result<()> {
     env_logger::init();
     log::info!("Starting");
 
-    let options: Options = Options::from_args();
+    let cli = Cli::from_args();
+    match cli.max_runtime {
+        None => run(cli.command)?,
+        Some(max_runtime) => {
+            // The command keeps running on its thread after the deadline, but returning from
+            // `main` ends the process
+            let (sender, receiver) = mpsc::channel();
+            let command = cli.command;
+            thread::spawn(move || sender.send(run(command)));
+            match receiver.recv_timeout(Duration::from_secs(max_runtime)) {
+                Ok(result) => result?,
+                Err(RecvTimeoutError::Timeout) => bail!(
+                    "The command did not finish within the maximum runtime of {} seconds",
+                    max_runtime
+                ),
+                Err(RecvTimeoutError::Disconnected) => bail!("The command panicked"),
+            }
+        }
+    }
+
+    log::info!("Done in {:?}", start.elapsed());
+
+    Ok(())
+}
+
+fn run(options: Options) -> Result<()> {
     match options {
         Options::TimePhrases {
             languages,
             phrases_output,
+            phrases_per_file,
+            hour_rollover,
+            prefix,
+            suffix,
+            format,
+            strict_precision,
+            offset,
+            filter,
+            unique,
+            start,
+            end,
+            round_to,
+            rounding_mode,
         } => {
-            time_phrases(languages, phrases_output)?;
+            time_phrases(
+                languages,
+                phrases_output,
+                phrases_per_file,
+                hour_rollover,
+                prefix,
+                suffix,
+                format,
+                strict_precision,
+                offset,
+                filter,
+                unique,
+                TimeRange::new(start, end)?,
+                round_to.map(|step| Rounding {
+                    step,
+                    mode: rounding_mode,
+                }),
+            )?;
         }
         Options::Grid {
             phrases_input,
             grid_output,
             allow_diagonal,
+            reading_axis,
             aspect_ratio,
             max_grid_bag_size,
             debug_tokens_svg,
+            tokens_graphml,
+            tokens_csv,
             chain_growth_head_space,
+            auto_chain_growth,
+            merge_objective,
             grid_html_output,
+            glyph_map,
+            sort_phrases,
+            max_grid_dimension,
+            max_pivots_per_letter,
+            phrase_separation,
+            synonyms,
+            separate_shared_boundaries,
+            frame,
+            frame_mode,
+            frame_letter,
+            max_aspect_padding,
+            max_letter_run,
+            fill_bias,
+            fill_distinct_cap,
+            minimal_output,
+            grid_stats,
+            origin_phrase,
+            proximity_weight,
+            direction_change_penalty,
+            grid_weight,
+            per_language,
+            coord_order,
+            insert_order,
+            max_tokens,
+            max_phrases,
+            threads,
+            seed,
+            dump_bag,
+            progress,
+            explain,
         } => {
-            grid(
-                phrases_input,
-                grid_output,
-                allow_diagonal,
-                aspect_ratio,
-                max_grid_bag_size,
-                debug_tokens_svg,
-                chain_growth_head_space,
-                grid_html_output,
-            )?;
+            let grid_input: GridInput = serde_json::from_str(&fs::read_to_string(&phrases_input)?)?;
+            let glyphs = load_glyph_map(glyph_map.as_deref())?;
+            let inputs = if per_language {
+                grid_input
+                    .partition_by_language()?
+                    .into_iter()
+                    .map(|(language, grid_input)| (Some(language.tag()), grid_input))
+                    .collect_vec()
+            } else {
+                vec![(None, grid_input)]
+            };
+
+            for (infix, grid_input) in inputs {
+                let with_infix = |path: &PathBuf| match infix {
+                    None => path.clone(),
+                    Some(infix) => path_with_infix(path, infix),
+                };
+                grid(
+                    grid_input,
+                    with_infix(&grid_output),
+                    allow_diagonal,
+                    reading_axis,
+                    aspect_ratio,
+                    max_grid_bag_size,
+                    debug_tokens_svg.as_ref().map(with_infix),
+                    tokens_graphml.as_ref().map(with_infix),
+                    tokens_csv.as_ref().map(with_infix),
+                    chain_growth_head_space,
+                    auto_chain_growth,
+                    merge_objective,
+                    grid_html_output.as_ref().map(with_infix),
+                    &glyphs,
+                    sort_phrases,
+                    max_grid_dimension,
+                    max_pivots_per_letter,
+                    phrase_separation,
+                    synonyms.as_deref(),
+                    separate_shared_boundaries,
+                    frame,
+                    frame_mode,
+                    frame_letter,
+                    max_aspect_padding,
+                    max_letter_run,
+                    fill_bias,
+                    fill_distinct_cap,
+                    minimal_output.as_ref().map(with_infix),
+                    grid_stats.as_ref().map(with_infix),
+                    origin_phrase,
+                    proximity_weight,
+                    direction_change_penalty,
+                    grid_weight,
+                    coord_order,
+                    insert_order,
+                    max_tokens,
+                    max_phrases,
+                    threads,
+                    seed,
+                    dump_bag.as_ref().map(with_infix),
+                    progress,
+                    explain.as_ref().map(with_infix),
+                )?;
+            }
         }
+        Options::Languages => {
+            for language in Language::all() {
+                println!("{}\texample: {}", language.tag(), language.example_spec());
+            }
+        }
+        Options::Refill {
+            grid_input,
+            grid_output,
+            seed,
+            max_letter_run,
+            fill_bias,
+            fill_distinct_cap,
+        } => refill(
+            grid_input,
+            grid_output,
+            seed,
+            FillConfig {
+                max_letter_run,
+                bias: fill_bias,
+                decoy_letters: None,
+            },
+            fill_distinct_cap,
+        )?,
+        Options::Example { input_type } => println!("{}", input_type.example_json()?),
         Options::LyricsPuzzle {
             lyrics_input,
             grid_input,
-            html_output,
+            minimal_input,
+            output,
+            format,
+            vtt_letters,
             ease_in,
             margin_before,
             margin_after,
             ease_out,
             discrete_time_step,
             letters_entering,
+            letters_per_second,
+            reveal_direction,
+            glyph_map,
+            validate_only,
         } => lyrics_puzzle(
             lyrics_input,
             grid_input,
-            html_output,
+            minimal_input,
+            output,
+            format,
+            vtt_letters,
             ease_in,
             margin_before,
             margin_after,
             ease_out,
             discrete_time_step,
             letters_entering,
+            letters_per_second,
+            reveal_direction,
+            glyph_map,
+            validate_only,
         )?,
     }
 
-    log::info!("Done in {:?}", start.elapsed());
+    Ok(())
+}
+
+fn refill(
+    grid_input: PathBuf,
+    grid_output: PathBuf,
+    seed: Option<u64>,
+    mut fill: FillConfig,
+    fill_distinct_cap: Option<usize>,
+) -> Result<()> {
+    ensure!(
+        fill.max_letter_run != Some(0),
+        "The maximum letter run must be at least 1"
+    );
+    let grid = GridOutput::load(&grid_input, None)?;
+    let mut random = match seed {
+        Some(seed) => SmallRng::seed_from_u64(seed),
+        None => SmallRng::from_entropy(),
+    };
+    if let Some(fill_distinct_cap) = fill_distinct_cap {
+        fill.cap_distinct_letters(fill_distinct_cap, &mut random)?;
+    }
+
+    let refilled = mhorloge::build_grid::refill(&grid, fill, &mut random)?;
+    fs::write(&grid_output, serde_json::to_string(&refilled)?)?;
 
     Ok(())
 }
@@ -178,140 +693,484 @@ fn main() -> Result<()> {
 fn lyrics_puzzle(
     lyrics_input: PathBuf,
     grid_input: PathBuf,
-    html_output: PathBuf,
+    minimal_input: Option<PathBuf>,
+    output: PathBuf,
+    format: LyricsFormat,
+    vtt_letters: bool,
     ease_in: i32,
     margin_before: i32,
     margin_after: i32,
     ease_out: i32,
     discrete_time_step: i32,
     letters_entering: f64,
+    letters_per_second: Option<f64>,
+    reveal_direction: RevealDirection,
+    glyph_map: Option<PathBuf>,
+    validate_only: bool,
 ) -> Result<()> {
     let phrases: LyricsPuzzleInput = serde_json::from_str(&fs::read_to_string(&lyrics_input)?)?;
-    let grid: GridOutput = serde_json::from_str(&fs::read_to_string(&grid_input)?)?;
+    let grid = GridOutput::load(&grid_input, minimal_input.as_deref())?;
+
+    if validate_only {
+        let problems = mhorloge::compile_lyrics_page::validate(&phrases, &grid);
+        for problem in &problems {
+            log::error!("{}", problem);
+        }
+        ensure!(problems.is_empty(), "Found {} problems", problems.len());
+        log::info!("The lyrics and the grid are consistent");
+        return Ok(());
+    }
 
+    if format == LyricsFormat::Vtt {
+        let grid = if vtt_letters { Some(&grid) } else { None };
+        fs::write(
+            &output,
+            mhorloge::compile_lyrics_page::compile_vtt(&phrases, grid)?,
+        )?;
+        return Ok(());
+    }
+
+    let timing_mode = match letters_per_second {
+        None => TimingMode::Ratio(letters_entering),
+        Some(rate) => {
+            ensure!(
+                rate > 0.0 && rate.is_finite(),
+                "--letters-per-second must be a positive number: {}",
+                rate
+            );
+            TimingMode::Rate(rate)
+        }
+    };
     let config = AnimationConfig {
         ease_in,
         margin_before,
         margin_after,
         ease_out,
-        letters_entering,
+        timing_mode,
         discrete_time_step,
+        reveal_direction,
     };
     fs::write(
-        &html_output,
-        compile_lyrics_page::compile_lyrics_page(&phrases, &grid, config)?,
+        &output,
+        mhorloge::compile_lyrics_page::compile_lyrics_page(
+            &phrases,
+            &grid,
+            config,
+            &load_glyph_map(glyph_map.as_deref())?,
+        )?,
     )?;
 
     Ok(())
 }
 
-fn time_phrases(languages: String, phrases_output: PathBuf) -> Result<()> {
+#[allow(clippy::too_many_arguments)]
+fn time_phrases(
+    languages: String,
+    phrases_output: PathBuf,
+    phrases_per_file: Option<usize>,
+    hour_rollover: HourRollover,
+    prefix: Option<String>,
+    suffix: Option<String>,
+    format: PhrasesFormat,
+    strict_precision: bool,
+    offset: TimeOffset,
+    filter: Option<Regex>,
+    unique: bool,
+    range: TimeRange,
+    rounding: Option<Rounding>,
+) -> Result<()> {
+    if let Some(rounding) = rounding {
+        ensure!(
+            (1..=60).contains(&rounding.step),
+            "--round-to must be between 1 and 60: {}",
+            rounding.step
+        );
+    }
     let mut language_specs = vec![];
-
-    for mut language_tag in languages.split(',') {
-        let precision;
-        match language_tag.find(':') {
-            None => precision = 1,
-            Some(pos) => {
-                precision = language_tag[pos + 1..].parse()?;
-                language_tag = &language_tag[..pos];
-            }
-        }
-
-        let language: Language = language_tag.parse()?;
-        language_specs.push((language, precision));
+    for language_tag in languages.split(',') {
+        let spec: LanguageSpec = language_tag.parse()?;
+        mhorloge::generate_phrases::check_precision(spec.precision, strict_precision)?;
+        language_specs.push(spec);
     }
 
-    let phrases = generate_phrases::generate_phrases(&language_specs);
+    let mut phrases = mhorloge::generate_phrases::generate_phrases(
+        &language_specs,
+        hour_rollover,
+        offset,
+        range,
+        rounding,
+    );
+    mhorloge::generate_phrases::decorate_phrases(
+        &mut phrases,
+        &parse_texts(prefix.as_deref().unwrap_or_default())?,
+        &parse_texts(suffix.as_deref().unwrap_or_default())?,
+    );
     log::info!("Generated {} phrases", phrases.len());
+    if let Some(filter) = &filter {
+        mhorloge::generate_phrases::filter_phrases(&mut phrases, filter);
+        log::info!("Kept {} phrases matching {}", phrases.len(), filter);
+    }
+    if unique {
+        let removed = mhorloge::generate_phrases::dedup_phrases(&mut phrases);
+        log::info!("Removed {} duplicate phrases", removed);
+    }
 
     if let Some(parent) = phrases_output.parent() {
         fs::create_dir_all(parent)?;
     }
-    fs::write(
-        &phrases_output,
-        serde_json::to_string_pretty(&TimePhrasesOutput { phrases })?,
-    )?;
+    match phrases_per_file {
+        None => fs::write(
+            &phrases_output,
+            TimePhrasesOutput { phrases }.encode(format)?,
+        )?,
+        Some(phrases_per_file) => {
+            ensure!(phrases_per_file > 0, "phrases_per_file must be positive");
+            let chunks = mhorloge::generate_phrases::split_phrases(phrases, phrases_per_file);
+            log::info!("Split phrases into {} files", chunks.len());
+
+            for (i, phrases) in chunks.into_iter().enumerate() {
+                fs::write(
+                    path_with_infix(&phrases_output, &i.to_string()),
+                    TimePhrasesOutput { phrases }.encode(format)?,
+                )?;
+            }
+        }
+    }
 
     Ok(())
 }
 
+fn load_glyph_map(path: Option<&Path>) -> Result<GlyphMap> {
+    match path {
+        None => Ok(GlyphMap::default()),
+        Some(path) => Ok(serde_json::from_str(&fs::read_to_string(path)?)?),
+    }
+}
+
+/// Parse words separated by spaces
+fn parse_texts(words: &str) -> Result<Vec<Text>> {
+    words.split_whitespace().map(|word| word.parse()).collect()
+}
+
+/// Insert `infix` before the extension of the file name: "output.json" becomes "output.infix.json"
+fn path_with_infix(path: &Path, infix: &str) -> PathBuf {
+    let mut file_name = path.file_stem().unwrap_or_default().to_owned();
+    file_name.push(format!(".{}", infix));
+    if let Some(extension) = path.extension() {
+        file_name.push(".");
+        file_name.push(extension);
+    }
+    path.with_file_name(file_name)
+}
+
 #[allow(clippy::too_many_arguments)]
 fn grid(
-    phrases_input: PathBuf,
+    mut grid_input: GridInput,
     grid_output: PathBuf,
     allow_diagonal: bool,
+    reading_axis: ReadingAxis,
     aspect_ratio: AspectRatio,
     max_grid_bag_size: usize,
     debug_tokens_svg: Option<PathBuf>,
-    chain_growth_head_space: i32,
+    tokens_graphml: Option<PathBuf>,
+    tokens_csv: Option<PathBuf>,
+    mut chain_growth_head_space: i32,
+    auto_chain_growth: bool,
+    merge_objective: MergeObjective,
     grid_html_output: Option<PathBuf>,
+    glyphs: &GlyphMap,
+    sort_phrases: PhraseOrder,
+    max_grid_dimension: Option<i16>,
+    max_pivots_per_letter: Option<usize>,
+    phrase_separation: bool,
+    synonyms: Option<&Path>,
+    separate_shared_boundaries: bool,
+    frame: i16,
+    frame_mode: FrameMode,
+    frame_letter: Option<char>,
+    max_aspect_padding: Option<f64>,
+    max_letter_run: Option<usize>,
+    fill_bias: FillBias,
+    fill_distinct_cap: Option<usize>,
+    minimal_output: Option<PathBuf>,
+    grid_stats: Option<PathBuf>,
+    origin_phrase: Option<usize>,
+    proximity_weight: f64,
+    direction_change_penalty: f64,
+    grid_weight: GridWeight,
+    coord_order: CoordOrder,
+    insert_order: InsertOrder,
+    max_tokens: Option<usize>,
+    max_phrases: Option<usize>,
+    threads: Option<usize>,
+    seed: Option<u64>,
+    dump_bag: Option<PathBuf>,
+    progress: bool,
+    explain: Option<PathBuf>,
 ) -> Result<()> {
-    let grid_input: GridInput = serde_json::from_str(&fs::read_to_string(&phrases_input)?)?;
+    ensure!(frame >= 0, "The frame cannot be negative");
+    ensure!(
+        max_letter_run != Some(0),
+        "The maximum letter run must be at least 1"
+    );
+    let max_aspect_padding = max_aspect_padding.unwrap_or(1.0);
+    ensure!(
+        (0.0..=1.0).contains(&max_aspect_padding),
+        "The maximum aspect padding must be between 0 and 1"
+    );
+    let frame_letter = frame_letter.map(Letter::try_from).transpose()?;
+    let disabled = grid_input.retain_enabled();
+    if disabled > 0 {
+        log::info!("Skipped {} disabled phrases", disabled);
+    }
+    let seed = match seed {
+        Some(seed) => seed,
+        None => {
+            let seed = grid_input.content_seed();
+            log::info!("Derived seed {} from the input", seed);
+            seed
+        }
+    };
+    if let Some(max_phrases) = max_phrases {
+        let random = &mut SmallRng::seed_from_u64(seed);
+        for phrase in grid_input.sample_phrases(max_phrases, random) {
+            log::warn!("Dropped phrase {}", phrase.texts.iter().format(" "));
+        }
+    }
 
     let mut phrase_book = PhraseBook::default();
+    if let Some(synonyms) = synonyms {
+        let groups: Vec<Vec<Text>> = serde_json::from_str(&fs::read_to_string(synonyms)?)?;
+        phrase_book.set_synonyms(&groups)?;
+    }
     for phrase in &grid_input.phrases {
         phrase_book.insert_phrase(phrase.texts.clone());
     }
     log::info!("Read {} phrases", phrase_book.phrases().len());
 
-    let token_graph = tokenize::tokenize(&phrase_book, chain_growth_head_space);
+    if auto_chain_growth {
+        chain_growth_head_space =
+            auto_chain_growth_head_space(&phrase_book, 0..=3, merge_objective);
+        log::info!(
+            "Picked chain_growth_head_space = {}",
+            chain_growth_head_space
+        );
+    }
+    let mut explanation = Explanation::default();
+    let token_graph = match &explain {
+        None => tokenize(&phrase_book, chain_growth_head_space, merge_objective),
+        Some(_) => tokenize_explained(
+            &phrase_book,
+            chain_growth_head_space,
+            merge_objective,
+            &mut explanation,
+        ),
+    };
     log::info!(
         "Formed token graph with {} tokens",
         token_graph.groups_len(),
     );
 
+    log::info!(
+        "Minimum grid span is {} tokens",
+        token_graph.longest_path().len()
+    );
+
+    mhorloge::build_grid::check_coordinate_range(
+        token_graph.groups().map(|(_, token)| token.letters_len()),
+    )?;
+
     if let Some(debug_tokens_svg) = &debug_tokens_svg {
         token_graph.svg(debug_tokens_svg)?;
     }
+    if let Some(tokens_graphml) = &tokens_graphml {
+        fs::write(tokens_graphml, token_graph.graphml())?;
+    }
+    if let Some(tokens_csv) = &tokens_csv {
+        let (nodes, edges) = mhorloge::tokenize::tokens_csv(&token_graph);
+        fs::write(path_with_infix(tokens_csv, "nodes"), nodes)?;
+        fs::write(tokens_csv, edges)?;
+    }
 
-    let best_grid = build_grid::build_grid(
+    let forbidden_cells = grid_input
+        .forbidden_cells
+        .iter()
+        .map(|&(x, y)| XY::new(x, y));
+    let (grid_bag, mut warnings) = mhorloge::build_grid::build_grid_bag(
+        Grid::with_forbidden_cells(forbidden_cells),
         phrase_book.phrases(),
         &token_graph,
-        max_grid_bag_size,
-        allow_diagonal,
-        aspect_ratio,
-    );
+        BuildGridConfig {
+            max_grid_bag_size,
+            insertion: InsertionConfig {
+                allow_diagonal,
+                reading_axis,
+                max_pivots_per_letter,
+                separate_phrases: phrase_separation,
+                separate_shared_boundaries,
+            },
+            aspect_ratio,
+            max_grid_dimension,
+            proximity_weight,
+            direction_change_penalty,
+            grid_weight,
+            insert_order,
+            max_tokens,
+            threads,
+            random_seed: Some(seed),
+            progress,
+        },
+    )?;
+    if let Some(dump_bag) = &dump_bag {
+        fs::write(dump_bag, serde_json::to_string(&grid_bag.entries())?)?;
+    }
+    let best_grid = grid_bag.best_grid().clone();
 
     let (width, height) = best_grid.size();
     log::info!("Build grid {}x{}", width, height);
 
-    let (aspect_width, aspect_height) = aspect_ratio.cover(width, height);
+    warnings.extend(mhorloge::build_grid::grid_warnings(
+        &best_grid,
+        &token_graph,
+    ));
+
+    if let Some(origin_phrase) = origin_phrase {
+        let phrase = phrase_book
+            .phrases()
+            .get(origin_phrase)
+            .with_context(|| format!("There is no phrase with index {}", origin_phrase))?;
+        let token = phrase_tokens(&token_graph, phrase)[0];
+        let offset = best_grid
+            .token_offset(token.id)
+            .expect("All tokens are in the grid");
+        if offset == XY::ORIGIN {
+            log::info!("Phrase {} starts at the top-left corner", origin_phrase);
+        } else {
+            log::warn!(
+                "Phrase {} starts {} columns and {} rows away from the top-left corner",
+                origin_phrase,
+                offset.x,
+                offset.y
+            );
+        }
+    }
+
+    if let Some(explain) = &explain {
+        explanation.explain_grid(&best_grid, &token_graph);
+        fs::write(explain, serde_json::to_string_pretty(&explanation)?)?;
+    }
+
+    let cover = match frame_mode {
+        FrameMode::Inset => aspect_ratio
+            .cover_capped(width + 2 * frame, height + 2 * frame, max_aspect_padding)
+            .map(|(framed_width, framed_height)| {
+                (framed_width - 2 * frame, framed_height - 2 * frame)
+            }),
+        FrameMode::Outside => aspect_ratio.cover_capped(width, height, max_aspect_padding),
+    };
+    let (aspect_width, aspect_height) = match cover {
+        Some(cover) => cover,
+        None => {
+            warnings.push(Warning::AspectNotHonored {
+                max_padding: max_aspect_padding,
+                width,
+                height,
+            });
+            (width, height)
+        }
+    };
+    for warning in &warnings {
+        log::warn!("{}", warning);
+    }
+    let mut random = SmallRng::seed_from_u64(seed);
     let mut final_grid = best_grid.clone();
-    final_grid.fill_to_size(aspect_width, aspect_height, &mut rand::thread_rng())?;
-    log::info!("Filled grid into {}x{}", aspect_width, aspect_height);
+    let mut fill = FillConfig {
+        max_letter_run,
+        bias: fill_bias,
+        decoy_letters: None,
+    };
+    if let Some(fill_distinct_cap) = fill_distinct_cap {
+        fill.cap_distinct_letters(fill_distinct_cap, &mut random)?;
+    }
+    final_grid.fill_to_size(aspect_width, aspect_height, fill, &mut random)?;
+    final_grid.add_frame(frame, frame_letter, fill, &mut random);
+    let (final_width, final_height) = final_grid.size();
+    log::info!("Filled grid into {}x{}", final_width, final_height);
 
+    // Only the forbidden cells are left empty by the fill. Like a frame of placeholders, they are
+    // not random, so a refill must keep them.
+    let is_frame = |x: i16, y: i16| {
+        frame_letter.is_some()
+            && (x < frame || y < frame || x >= final_width - frame || y >= final_height - frame)
+    };
+    let mut fixed_cells = vec![];
     let final_letters = final_grid
         .to_letters()
         .into_iter()
-        .map(|row| {
+        .zip(0..)
+        .map(|(row, y)| {
             row.into_iter()
-                .map(|letter| letter.expect("Grid is totally filled"))
-                .collect()
+                .zip(0..)
+                .map(|(letter, x)| {
+                    if letter.is_none() || is_frame(x, y) {
+                        fixed_cells.push((x, y));
+                    }
+                    letter.or(frame_letter).context(
+                        "The grid has forbidden cells, which require a --frame-letter placeholder",
+                    )
+                })
+                .collect::<Result<_>>()
         })
-        .collect();
+        .collect::<Result<_>>()?;
 
-    let final_phrases = phrase_book
+    let mut final_phrases = phrase_book
         .phrases()
         .iter()
         .map(|phrase| GridOutputPhrase {
             words: phrase_to_letter_positions(&token_graph, &final_grid, phrase),
         })
-        .collect();
+        .collect_vec();
+
+    if sort_phrases == PhraseOrder::Position {
+        // Sort the input phrases as well, to keep them aligned for the HTML output
+        let (input_phrases, output_phrases) = grid_input
+            .phrases
+            .into_iter()
+            .zip(final_phrases)
+            .sorted_by_key(|(_, output_phrase)| output_phrase.first_position())
+            .unzip();
+        grid_input.phrases = input_phrases;
+        final_phrases = output_phrases;
+    }
 
     let grid = GridOutput {
+        version: GRID_OUTPUT_VERSION,
         minimal_grid: best_grid.to_letters(),
         grid: final_letters,
+        fixed_cells,
         phrases: final_phrases,
     };
 
     if let Some(parent) = grid_output.parent() {
         fs::create_dir_all(parent)?;
     }
-    fs::write(&grid_output, serde_json::to_string(&grid)?)?;
+    let written_grid = grid.clone().with_coord_order(coord_order);
+    match minimal_output {
+        None => fs::write(&grid_output, serde_json::to_string(&written_grid)?)?,
+        Some(minimal_output) => {
+            let offset = best_grid.top_left() - final_grid.top_left();
+            let (minimal, filled) = written_grid.split(coord_order.apply((offset.x, offset.y)));
+            fs::write(&grid_output, serde_json::to_string(&filled)?)?;
+            fs::write(&minimal_output, serde_json::to_string(&minimal)?)?;
+        }
+    }
+
+    if let Some(grid_stats) = grid_stats {
+        fs::write(&grid_stats, serde_json::to_string(&grid.stats())?)?;
+    }
 
     if let Some(grid_html_output) = grid_html_output {
-        fs::write(&grid_html_output, compile_html(&grid_input, &grid))?;
+        fs::write(&grid_html_output, compile_html(&grid_input, &grid, glyphs))?;
     }
 
     Ok(())
@@ -324,11 +1183,9 @@ fn phrase_to_letter_positions(
 ) -> Vec<GridOutputWord> {
     let top_left = grid.top_left();
 
-    phrase
-        .words
-        .iter()
-        .map(|&word| {
-            let token = token_graph.group(word).1;
+    phrase_tokens(token_graph, phrase)
+        .into_iter()
+        .map(|token| {
             let letters = grid
                 .positions_for_token(token.id)
                 .expect("The token must be present")
@@ -337,7 +1194,10 @@ fn phrase_to_letter_positions(
                     (abs_pos.x, abs_pos.y)
                 })
                 .collect();
-            GridOutputWord { letters }
+            GridOutputWord {
+                letters,
+                text: token.text().to_string(),
+            }
         })
         .collect()
 }
@@ -24,6 +24,19 @@ impl AspectRatio {
             (width, height_for_ratio)
         }
     }
+
+    /// Like [`AspectRatio::cover()`], but return `None` when more than `max_padding` of the cells
+    /// of the covering rectangle would be added to the given one.
+    pub fn cover_capped(self, width: i16, height: i16, max_padding: f64) -> Option<(i16, i16)> {
+        let (cover_width, cover_height) = self.cover(width, height);
+        let cover_area = cover_width as f64 * cover_height as f64;
+        let added = cover_area - width as f64 * height as f64;
+        if added > max_padding * cover_area {
+            None
+        } else {
+            Some((cover_width, cover_height))
+        }
+    }
 }
 
 impl FromStr for AspectRatio {
@@ -71,4 +84,18 @@ mod tests {
 
         assert_eq!(ratio.cover(17, 10), (18, 10));
     }
+
+    #[test]
+    fn cover_capped() {
+        let ratio = AspectRatio {
+            horizontal: 1,
+            vertical: 1,
+        };
+
+        // Covering 10x2 needs 80 of the 100 cells
+        assert_eq!(ratio.cover_capped(10, 2, 1.0), Some((10, 10)));
+        assert_eq!(ratio.cover_capped(10, 2, 0.8), Some((10, 10)));
+        assert_eq!(ratio.cover_capped(10, 2, 0.5), None);
+        assert_eq!(ratio.cover_capped(10, 9, 0.5), Some((10, 10)));
+    }
 }
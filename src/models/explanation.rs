@@ -0,0 +1,99 @@
+use crate::models::grid::Grid;
+use crate::models::merge_dag::MergeDag;
+use crate::models::positioned_token::XY;
+use crate::models::token::{Token, TokenId};
+use crate::models::word::WordId;
+use itertools::Itertools;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// A machine-readable record of the decisions taken while building a grid, to audit why the tokens
+/// were formed and placed as they were
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Explanation {
+    /// The sequences of words that repeat in the phrases, in the order they were merged
+    pub sequences: Vec<ExplainedSequence>,
+    /// Each attempt to merge a word into a group of words, in order
+    pub merges: Vec<ExplainedMerge>,
+    /// The tokens of the chosen grid, in the order they were inserted
+    pub insert_order: Vec<String>,
+    /// Where each token of the chosen grid was placed, in the order they were inserted
+    pub placements: Vec<ExplainedPlacement>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainedSequence {
+    pub texts: Vec<String>,
+    /// How many times the sequence appears in the phrases
+    pub instances: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainedMerge {
+    /// The text shared by both groups
+    pub text: String,
+    /// The chain size of the largest group, before the merge
+    pub old_chain_size: i32,
+    /// The chain size of the merged group
+    pub new_chain_size: i32,
+    pub max_chain_size: i32,
+    pub decision: MergeDecision,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MergeDecision {
+    Accepted,
+    /// The merge objective rejects the growth of the chain
+    ChainTooLong,
+    /// One group comes before the other in some phrase, so merging them would create a cycle
+    Cycle,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExplainedPlacement {
+    pub token: String,
+    /// The position of the first letter, relative to the top-left corner of the minimal grid
+    pub start: (i16, i16),
+    pub direction: String,
+    /// The tokens placed before this one that share a cell with it, which pinned its position
+    pub pivots: Vec<String>,
+}
+
+impl Explanation {
+    /// Record where each token of `grid` was placed
+    pub fn explain_grid(&mut self, grid: &Grid, token_graph: &MergeDag<WordId, Token>) {
+        let tokens: BTreeMap<TokenId, &Token> = token_graph
+            .groups()
+            .map(|(_, token)| (token.id, token))
+            .collect();
+        let name = |token_id: TokenId| tokens[&token_id].to_string();
+        let top_left = grid.top_left();
+
+        let positioned_tokens = grid.positioned_tokens();
+        self.insert_order = positioned_tokens
+            .iter()
+            .map(|positioned| name(positioned.token_id()))
+            .collect();
+        self.placements = positioned_tokens
+            .iter()
+            .enumerate()
+            .map(|(i, positioned)| {
+                let cells = positioned.iter_pos().collect_vec();
+                let pivots = positioned_tokens[..i]
+                    .iter()
+                    .filter(|before| before.iter_pos().any(|pos| cells.contains(&pos)))
+                    .map(|before| name(before.token_id()))
+                    .collect();
+                let XY { x, y } = positioned.start() - top_left;
+
+                ExplainedPlacement {
+                    token: name(positioned.token_id()),
+                    start: (x, y),
+                    direction: format!("{:?}", positioned.direction()),
+                    pivots,
+                }
+            })
+            .collect();
+    }
+}
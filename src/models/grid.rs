@@ -1,34 +1,137 @@
 use crate::models::letter::Letter;
-use crate::models::position_restriction::PositionRestriction;
+use crate::models::position_restriction::{PositionRestriction, ReadingAxis};
 use crate::models::positioned_token::{Direction, OrientedToken, PositionedToken, XY};
+use crate::models::text::Text;
 use crate::models::token::{Token, TokenId};
 use crate::models::token_relations::TokenRelations;
 use anyhow::ensure;
 use anyhow::Result;
+use anyhow::{anyhow, Error};
+use itertools::Itertools;
+use rand::seq::SliceRandom;
 use rand::Rng;
-use std::collections::{BTreeSet, HashMap};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt;
 use std::fmt::Write;
+use std::hash::{Hash, Hasher};
+use std::mem;
 use std::ops::RangeInclusive;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Configure how tokens can be inserted into a grid
+#[derive(Debug, Clone, Copy)]
+pub struct InsertionConfig {
+    /// Whether tokens can be positioned diagonally
+    pub allow_diagonal: bool,
+    pub reading_axis: ReadingAxis,
+    /// The maximum number of existing letters tried as pivots for each letter of a token. When
+    /// there are more candidates, they are randomly sampled.
+    pub max_pivots_per_letter: Option<usize>,
+    /// Whether a token can only touch the tokens of the phrases it does not belong to, in any of
+    /// the 8 directions, by crossing them
+    pub separate_phrases: bool,
+    /// Whether a token can start right next to the end of the token before it in a phrase when
+    /// they have the same letter at that boundary. See
+    /// [`PositionRestriction::separate_shared_boundaries()`].
+    pub separate_shared_boundaries: bool,
+}
+
+/// Configure how the empty cells of a grid are filled with random letters
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FillConfig {
+    /// The maximum number of identical letters in a horizontal or vertical run that the random
+    /// letters can create
+    pub max_letter_run: Option<usize>,
+    pub bias: FillBias,
+    /// The letters the random fill can use, indexed by letter. When absent, all letters are used.
+    pub decoy_letters: Option<[bool; 26]>,
+}
+
+/// Determine how the random letters relate to the letters nearby that were already in the grid
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum FillBias {
+    /// All letters are equally likely
+    #[default]
+    Neutral,
+    /// Favor the letters nearby, so that the words blend in
+    Blend,
+    /// Avoid the letters nearby, so that the words stand out
+    Contrast,
+}
+
+/// How far, in cells along each axis, the letters are considered nearby for [`FillBias`]
+const FILL_BIAS_RADIUS: i16 = 2;
+
+/// How much each nearby letter changes the weight of that letter for [`FillBias`]
+const FILL_BIAS_STRENGTH: f64 = 4.;
 
 #[derive(Debug, Clone)]
 pub struct Grid {
-    letter_by_pos: HashMap<XY, Letter>,
+    /// Ordered, so that iterating over the letters, like when sampling pivots, is reproducible
+    letter_by_pos: BTreeMap<XY, Letter>,
     tokens: Vec<PositionedToken>,
     /// The extremes of the bounding rectangle of the inserted letters. This rectangle does not
     /// depend on the desired aspect ratio.
     top_left: XY,
     bottom_right: XY,
+    /// The cells that must never hold a letter, shared by all grids derived from this one
+    forbidden_cells: Arc<BTreeSet<XY>>,
 }
 
 impl Grid {
     pub fn new() -> Self {
         Self {
-            letter_by_pos: HashMap::new(),
+            letter_by_pos: BTreeMap::new(),
             tokens: Vec::new(),
             top_left: XY::new(i16::MAX, i16::MAX),
             bottom_right: XY::new(i16::MIN, i16::MIN),
+            forbidden_cells: Arc::default(),
+        }
+    }
+
+    /// Build an empty grid in which no token can be inserted over the given cells and that is not
+    /// filled there
+    pub fn with_forbidden_cells(forbidden_cells: impl IntoIterator<Item = XY>) -> Self {
+        Self {
+            forbidden_cells: Arc::new(forbidden_cells.into_iter().collect()),
+            ..Self::new()
+        }
+    }
+
+    /// Return whether the cell must never hold a letter
+    pub fn is_forbidden(&self, pos: XY) -> bool {
+        self.forbidden_cells.contains(&pos)
+    }
+
+    /// Build a grid from rows of letters, like the ones returned by [`Grid::to_letters()`]. The
+    /// top-left cell is at the origin. The tokens are not known, so none are positioned.
+    pub fn from_letters(letters: &[Vec<Option<Letter>>]) -> Self {
+        let height = letters.len() as i16;
+        let width = letters.iter().map(|row| row.len()).max().unwrap_or(0) as i16;
+
+        let mut letter_by_pos = BTreeMap::new();
+        for (y, row) in letters.iter().enumerate() {
+            for (x, &letter) in row.iter().enumerate() {
+                if let Some(letter) = letter {
+                    letter_by_pos.insert(XY::new(x as i16, y as i16), letter);
+                }
+            }
         }
+
+        Grid {
+            letter_by_pos,
+            tokens: Vec::new(),
+            top_left: XY::ORIGIN,
+            bottom_right: XY::new(width - 1, height - 1),
+            forbidden_cells: Arc::default(),
+        }
+    }
+
+    /// Return whether this grid has no letter, even if it has a bounding box
+    pub fn is_empty(&self) -> bool {
+        self.letter_by_pos.is_empty()
     }
 
     /// Return the number of determined letters of this grid
@@ -44,8 +147,12 @@ impl Grid {
         )
     }
 
-    /// Returns `(width, height)` of the bounding box of the grid
+    /// Returns `(width, height)` of the bounding box of the grid, which is `(0, 0)` for a new grid
     pub fn size(&self) -> (i16, i16) {
+        if self.top_left.x > self.bottom_right.x {
+            return (0, 0);
+        }
+
         let (x, y) = self.space();
         let width = x.end() - x.start() + 1;
         let height = y.end() - y.start() + 1;
@@ -69,23 +176,48 @@ impl Grid {
         &self,
         relations: &TokenRelations,
         token: &Token,
-        allow_diagonal: bool,
+        config: InsertionConfig,
+        random: &mut impl Rng,
     ) -> Vec<Grid> {
         // Enumerate all insertions that use a valid pivot. A `BTreeSet` is used to deduplicate
         // them, in case a single insertion covers multiple pivots simultaneously
         let mut insertions = BTreeSet::new();
+        let is_valid = |positioned| {
+            self.check_letters(token, positioned)
+                && (!config.separate_phrases || self.check_separation(relations, token, positioned))
+        };
 
-        for oriented in OrientedToken::orientations(token, allow_diagonal) {
-            let restrictions = PositionRestriction::new(relations, &self.tokens, oriented);
+        for oriented in OrientedToken::orientations(token, config.allow_diagonal) {
+            let mut restrictions =
+                PositionRestriction::new(relations, &self.tokens, oriented, config.reading_axis);
+            if config.separate_shared_boundaries {
+                restrictions.separate_shared_boundaries(
+                    relations,
+                    &self.tokens,
+                    token,
+                    oriented,
+                    |pos| self.get(pos),
+                );
+            }
 
             // Test insertions that use a pivot
-            for (letter_index, &letter) in token.text.letters().iter().enumerate() {
+            for (letter_index, &letter) in token.text().letters().iter().enumerate() {
                 let n = letter_index as i16;
 
-                for pivot in self.pos_by_letter(letter) {
+                let mut pivots = self.pos_by_letter(letter).collect_vec();
+                if let Some(max_pivots) = config.max_pivots_per_letter {
+                    if pivots.len() > max_pivots {
+                        pivots = pivots
+                            .choose_multiple(random, max_pivots)
+                            .copied()
+                            .collect();
+                    }
+                }
+
+                for pivot in pivots {
                     let start = pivot - oriented.direction().as_xy() * n;
                     let positioned = PositionedToken::new(oriented, start);
-                    if restrictions.is_valid_start(start) && self.check_letters(token, positioned) {
+                    if restrictions.is_valid_start(start) && is_valid(positioned) {
                         insertions.insert(positioned);
                     }
                 }
@@ -93,28 +225,29 @@ impl Grid {
 
             // Test insertions that do not use any pivot: find a valid first insertion and also try
             // a more spaced one
-            for scan_dir in [
-                Direction::Horizontal,
-                Direction::Vertical,
-                Direction::Diagonal,
-            ] {
-                let mut start = restrictions.min_start().unwrap_or(XY::ORIGIN);
-                loop {
+            for scan_dir in Direction::all(true) {
+                // Scan backwards from the latest start, when there is one
+                let (mut start, step) = match restrictions.max_start() {
+                    Some(max_start) => (max_start, scan_dir.as_xy() * -1),
+                    None => (
+                        restrictions.min_start().unwrap_or(XY::ORIGIN),
+                        scan_dir.as_xy(),
+                    ),
+                };
+                while restrictions.is_in_range(start) {
                     let positioned = PositionedToken::new(oriented, start);
-                    if restrictions.is_valid_start(start) && self.check_letters(token, positioned) {
+                    if restrictions.is_valid_start(start) && is_valid(positioned) {
                         insertions.insert(positioned);
 
-                        let start_2 = start + scan_dir.as_xy() * 3;
+                        let start_2 = start + step * 3;
                         let positioned_2 = PositionedToken::new(oriented, start_2);
-                        if restrictions.is_valid_start(start_2)
-                            && self.check_letters(token, positioned_2)
-                        {
+                        if restrictions.is_valid_start(start_2) && is_valid(positioned_2) {
                             insertions.insert(positioned_2);
                         }
                         break;
                     }
 
-                    start += scan_dir.as_xy();
+                    start += step;
                 }
             }
         }
@@ -139,22 +272,37 @@ impl Grid {
         let (dx, dy) = self.space();
 
         let mut grid = Vec::with_capacity(dy.len());
-        for y in dy {
-            let mut row = Vec::with_capacity(dx.len());
-            for x in dx.clone() {
-                row.push(self.get(XY::new(x, y)));
-            }
-            grid.push(row);
+        let mut cells = self.iter_cells().map(|(_, letter)| letter);
+        for _ in dy {
+            grid.push(cells.by_ref().take(dx.len()).collect());
         }
 
         grid
     }
 
-    /// Fill this instance with letters so that it has at least the given size.
+    /// Iterate over every cell in the bounding box, row by row, with its position and letter
+    pub fn iter_cells(&self) -> impl Iterator<Item = (XY, Option<Letter>)> + '_ {
+        let (dx, dy) = self.space();
+        dy.flat_map(move |y| dx.clone().map(move |x| XY::new(x, y)))
+            .map(move |pos| (pos, self.get(pos)))
+    }
+
+    /// Fill this instance with letters so that it has at least the given size. When
+    /// `max_letter_run` is given, the random letters avoid extending a horizontal or vertical run
+    /// of identical letters beyond it. If no allowed letter can avoid it, for example with few
+    /// distinct letters, a random allowed letter is used anyway and a warning is logged. The
+    /// random letters are biased by the letters nearby that were already in the grid. The
+    /// forbidden cells are left empty.
     ///
     /// # Error
     /// Returns an error if the given size is smaller than the current grid
-    pub fn fill_to_size(&mut self, width: i16, height: i16, random: &mut impl Rng) -> Result<()> {
+    pub fn fill_to_size(
+        &mut self,
+        width: i16,
+        height: i16,
+        config: FillConfig,
+        random: &mut impl Rng,
+    ) -> Result<()> {
         let (current_width, current_height) = self.size();
 
         ensure!(width >= current_width);
@@ -168,21 +316,207 @@ impl Grid {
         let start_y = self.top_left.y - (padding_y + 1) / 2;
         let end_y = self.bottom_right.y + padding_y / 2;
 
+        // Expand the bounding box first, so that the letter runs can be scanned while filling
+        self.top_left = XY::new(start_x, start_y);
+        self.bottom_right = XY::new(end_x, end_y);
+
+        let previous_letters = self.previous_letters(config.bias);
+        let mut long_runs = 0;
         for y in start_y..=end_y {
             for x in start_x..=end_x {
-                let pos = XY::new(x, y);
-                self.letter_by_pos
-                    .entry(pos)
-                    .or_insert_with(|| random.gen());
+                if !self.fill_cell(XY::new(x, y), None, config, &previous_letters, random) {
+                    long_runs += 1;
+                }
             }
         }
-
-        self.top_left = XY::new(start_x, start_y);
-        self.bottom_right = XY::new(end_x, end_y);
+        config.warn_long_runs(long_runs);
 
         Ok(())
     }
 
+    /// Expand the grid by `size` cells on each side. The new cells are set to `placeholder` or,
+    /// when absent, to random letters, like [`Grid::fill_to_size()`].
+    pub fn add_frame(
+        &mut self,
+        size: i16,
+        placeholder: Option<Letter>,
+        config: FillConfig,
+        random: &mut impl Rng,
+    ) {
+        self.top_left = self.top_left - XY::new(size, size);
+        self.bottom_right += XY::new(size, size);
+
+        let previous_letters = self.previous_letters(config.bias);
+        let (x_range, y_range) = self.space();
+        let mut long_runs = 0;
+        for y in y_range {
+            for x in x_range.clone() {
+                if !self.fill_cell(
+                    XY::new(x, y),
+                    placeholder,
+                    config,
+                    &previous_letters,
+                    random,
+                ) {
+                    long_runs += 1;
+                }
+            }
+        }
+        config.warn_long_runs(long_runs);
+    }
+
+    /// Return the letters that bias the fill, which are all the current ones unless the bias is
+    /// neutral
+    fn previous_letters(&self, bias: FillBias) -> BTreeMap<XY, Letter> {
+        match bias {
+            FillBias::Neutral => BTreeMap::new(),
+            FillBias::Blend | FillBias::Contrast => self.letter_by_pos.clone(),
+        }
+    }
+
+    /// Set the letter of an empty cell to `placeholder` or, when absent, to a random letter
+    /// respecting `config`. Forbidden cells are left empty. Return `false` when no allowed letter
+    /// could respect `config.max_letter_run`, so that the chosen one extends a run beyond it.
+    fn fill_cell(
+        &mut self,
+        pos: XY,
+        placeholder: Option<Letter>,
+        config: FillConfig,
+        previous_letters: &BTreeMap<XY, Letter>,
+        random: &mut impl Rng,
+    ) -> bool {
+        if self.letter_by_pos.contains_key(&pos) || self.is_forbidden(pos) {
+            return true;
+        }
+
+        let mut respects_run = true;
+        let letter = match (placeholder, config.max_letter_run, config.bias) {
+            (Some(letter), _, _) => letter,
+            (None, None, FillBias::Neutral) if config.decoy_letters.is_none() => random.gen(),
+            (None, max_letter_run, bias) => {
+                let allowed = Letter::all()
+                    .filter(|&letter| config.allows(letter))
+                    .collect_vec();
+                let candidates = allowed
+                    .iter()
+                    .copied()
+                    .filter(|&letter| match max_letter_run {
+                        None => true,
+                        Some(max_letter_run) => {
+                            self.run_len(pos, letter, XY::new(1, 0)) <= max_letter_run
+                                && self.run_len(pos, letter, XY::new(0, 1)) <= max_letter_run
+                        }
+                    })
+                    .collect_vec();
+
+                // Only a handful of letters can be excluded by the neighbors
+                let chosen = if bias == FillBias::Neutral {
+                    candidates.choose(random).copied()
+                } else {
+                    let mut nearby = [0; 26];
+                    for dy in -FILL_BIAS_RADIUS..=FILL_BIAS_RADIUS {
+                        for dx in -FILL_BIAS_RADIUS..=FILL_BIAS_RADIUS {
+                            if let Some(&letter) = previous_letters.get(&(pos + XY::new(dx, dy))) {
+                                nearby[letter as usize] += 1;
+                            }
+                        }
+                    }
+                    candidates
+                        .choose_weighted(random, |&letter| bias.weight(nearby[letter as usize]))
+                        .ok()
+                        .copied()
+                };
+                chosen
+                    .or_else(|| candidates.choose(random).copied())
+                    .unwrap_or_else(|| {
+                        respects_run = false;
+                        *allowed.choose(random).expect("Some letter is allowed")
+                    })
+            }
+        };
+
+        self.letter_by_pos.insert(pos, letter);
+        respects_run
+    }
+
+    /// Return the size of the run of `letter` along `step` that would contain `pos`, if it held
+    /// that letter
+    fn run_len(&self, pos: XY, letter: Letter, step: XY) -> usize {
+        let count_from = |step: XY| {
+            self.scan_line(pos + step, step)
+                .take_while(|&(_, some_letter)| some_letter == Some(letter))
+                .count()
+        };
+
+        1 + count_from(step) + count_from(step * -1)
+    }
+
+    /// Iterate over the cells from `start`, moving by `step` until leaving the bounding box
+    pub fn scan_line(
+        &self,
+        start: XY,
+        step: XY,
+    ) -> impl Iterator<Item = (XY, Option<Letter>)> + '_ {
+        let (x_range, y_range) = self.space();
+        let mut next = Some(start);
+        std::iter::from_fn(move || {
+            let pos = next?;
+            if !x_range.contains(&pos.x) || !y_range.contains(&pos.y) {
+                return None;
+            }
+            next = Some(pos + step);
+            Some((pos, self.get(pos)))
+        })
+    }
+
+    /// Translate all letters and tokens of this grid by `offset`
+    pub fn shift(&mut self, offset: XY) {
+        if self.is_empty() {
+            return;
+        }
+        self.letter_by_pos = mem::take(&mut self.letter_by_pos)
+            .into_iter()
+            .map(|(pos, letter)| (pos + offset, letter))
+            .collect();
+        for token in &mut self.tokens {
+            *token = token.shifted(offset);
+        }
+        self.top_left += offset;
+        self.bottom_right += offset;
+        if !self.forbidden_cells.is_empty() {
+            self.forbidden_cells = Arc::new(
+                self.forbidden_cells
+                    .iter()
+                    .map(|&pos| pos + offset)
+                    .collect(),
+            );
+        }
+    }
+
+    /// Return the part of this grid inside the given region, keeping the same coordinates. Tokens
+    /// that are not fully inside the region are dropped, but their letters are kept.
+    pub fn subgrid(&self, x_range: RangeInclusive<i16>, y_range: RangeInclusive<i16>) -> Grid {
+        let contains = |pos: XY| x_range.contains(&pos.x) && y_range.contains(&pos.y);
+
+        Grid {
+            letter_by_pos: self
+                .letter_by_pos
+                .iter()
+                .filter(|(&pos, _)| contains(pos))
+                .map(|(&pos, &letter)| (pos, letter))
+                .collect(),
+            tokens: self
+                .tokens
+                .iter()
+                .copied()
+                .filter(|token| contains(token.start()) && contains(token.end()))
+                .collect(),
+            top_left: XY::new(*x_range.start(), *y_range.start()),
+            bottom_right: XY::new(*x_range.end(), *y_range.end()),
+            forbidden_cells: self.forbidden_cells.clone(),
+        }
+    }
+
     pub fn positions_for_token(&self, token: TokenId) -> Option<impl Iterator<Item = XY> + '_> {
         let positioned = self
             .tokens
@@ -196,10 +530,162 @@ impl Grid {
         self.top_left
     }
 
-    fn insert(&mut self, token: &Token, positioned: PositionedToken) {
+    /// Return where the first letter of the token is, relative to the top-left corner, like in the
+    /// coordinates of the output
+    pub fn token_offset(&self, token: TokenId) -> Option<XY> {
+        let start = self.positions_for_token(token)?.next()?;
+        Some(start - self.top_left)
+    }
+
+    /// Return a hash of the layout of this grid, that does not depend on where the grid is nor on
+    /// the order the tokens were inserted. Equal grids have the same hash.
+    pub fn canonical_hash(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        self.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Return the size of the bounding box, the letters and the tokens of this grid relative to its
+    /// top-left corner, in a deterministic order
+    fn canonical_layout(&self) -> (XY, Vec<(XY, Letter)>, Vec<PositionedToken>) {
+        if self.is_empty() {
+            return (XY::ORIGIN, vec![], vec![]);
+        }
+
+        let origin = XY::ORIGIN - self.top_left;
+        let letters = self
+            .letter_by_pos
+            .iter()
+            .map(|(&pos, &letter)| (pos + origin, letter))
+            .sorted()
+            .collect();
+        let tokens = self
+            .tokens
+            .iter()
+            .map(|token| token.shifted(origin))
+            .sorted()
+            .collect();
+        (self.bottom_right + origin, letters, tokens)
+    }
+
+    /// Return the places where one of the `real` words can be read, starting at a position and
+    /// going in a direction, through cells that do not all belong to a single token. This happens
+    /// when the word is spliced from parts of neighboring or crossing tokens and could mislead a
+    /// reader. Words with a single letter are ignored.
+    pub fn confusable_reads(&self, real: &[Text]) -> Vec<(XY, Direction, Text)> {
+        let token_cells = self
+            .tokens
+            .iter()
+            .map(|token| token.iter_pos().collect::<BTreeSet<_>>())
+            .collect_vec();
+        let words: BTreeSet<_> = real
+            .iter()
+            .filter(|text| text.letters().len() > 1)
+            .collect();
+
+        let mut reads = vec![];
+        for (start, letter) in self.iter_cells() {
+            for word in &words {
+                if letter != Some(word.letters()[0]) {
+                    continue;
+                }
+
+                for direction in Direction::all(true) {
+                    let cells = (0..word.letters().len() as i16)
+                        .map(|i| start + direction.as_xy() * i)
+                        .collect_vec();
+                    let spells = cells
+                        .iter()
+                        .zip(word.letters())
+                        .all(|(&pos, &letter)| self.get(pos) == Some(letter));
+                    let in_one_token = token_cells
+                        .iter()
+                        .any(|token_cells| cells.iter().all(|pos| token_cells.contains(pos)));
+                    if spells && !in_one_token {
+                        reads.push((start, direction, (*word).clone()));
+                    }
+                }
+            }
+        }
+
+        reads
+    }
+
+    /// Return the sum of the distances between the centers of the tokens that appear together in
+    /// phrases, each weighted by its number of phrases. Tokens that are not in this grid are
+    /// ignored.
+    pub fn co_occurrence_distance(&self, co_occurrences: &[(TokenId, TokenId, u16)]) -> f64 {
+        let centers: BTreeMap<_, _> = self
+            .tokens
+            .iter()
+            .map(|token| {
+                let start = token.start();
+                let end = token.end();
+                let center_x = (start.x + end.x) as f64 / 2.;
+                let center_y = (start.y + end.y) as f64 / 2.;
+                (token.token_id(), (center_x, center_y))
+            })
+            .collect();
+
+        co_occurrences
+            .iter()
+            .filter_map(|&(a, b, count)| {
+                let (ax, ay) = centers.get(&a)?;
+                let (bx, by) = centers.get(&b)?;
+                Some(count as f64 * (ax - bx).hypot(ay - by))
+            })
+            .sum()
+    }
+
+    /// Return how many times a token is followed by another one in a different direction, each
+    /// weighted by its number of occurrences. Single-letter tokens have no direction, so they
+    /// never change it. Tokens that are not in this grid are ignored.
+    pub fn direction_changes(&self, successions: &[(TokenId, TokenId, u16)]) -> u32 {
+        let directions: BTreeMap<_, _> = self
+            .tokens
+            .iter()
+            .map(|token| (token.token_id(), token.direction()))
+            .collect();
+
+        successions
+            .iter()
+            .filter(|&&(before, after, _)| {
+                match (directions.get(&before), directions.get(&after)) {
+                    (Some(&before), Some(&after)) => {
+                        before != Direction::Point && after != Direction::Point && before != after
+                    }
+                    _ => false,
+                }
+            })
+            .map(|&(_, _, count)| count as u32)
+            .sum()
+    }
+
+    /// Return the tokens positioned in this grid, in the order they were inserted
+    pub fn positioned_tokens(&self) -> &[PositionedToken] {
+        &self.tokens
+    }
+
+    /// Return the number of tokens positioned in this grid
+    pub fn num_tokens(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Return whether the given token was already inserted in this grid
+    pub fn contains_token(&self, token: TokenId) -> bool {
+        self.tokens
+            .iter()
+            .any(|positioned| positioned.token_id() == token)
+    }
+
+    /// Insert the token in the given position.
+    ///
+    /// # Panics
+    /// It will panic if the token letters conflict with the existing ones
+    pub fn insert(&mut self, token: &Token, positioned: PositionedToken) {
         for (pos, letter) in positioned.iter(token) {
             let prev_letter = self.letter_by_pos.insert(pos, letter);
-            assert!(prev_letter == None || prev_letter == Some(letter));
+            assert!(prev_letter.is_none() || prev_letter == Some(letter));
 
             self.top_left.x = self.top_left.x.min(pos.x);
             self.top_left.y = self.top_left.y.min(pos.y);
@@ -215,17 +701,177 @@ impl Grid {
         for (xy, new_letter) in positioned.iter(token) {
             let current_letter = self.letter_by_pos.get(&xy).copied();
 
-            if current_letter != None && current_letter != Some(new_letter) {
+            if current_letter.is_some() && current_letter != Some(new_letter) {
+                return false;
+            }
+
+            if self.is_forbidden(xy) {
                 return false;
             }
         }
 
         true
     }
+
+    /// Check that the positioned token does not touch any token that never appears in the same
+    /// phrase as it, unless they share a cell
+    fn check_separation(
+        &self,
+        relations: &TokenRelations,
+        token: &Token,
+        positioned: PositionedToken,
+    ) -> bool {
+        let new_positions = positioned.iter_pos().collect_vec();
+
+        self.tokens
+            .iter()
+            .filter(|other| {
+                other.token_id() != token.id && !relations.coexist(token.id, other.token_id())
+            })
+            .all(|other| {
+                let mut touches = false;
+                for other_pos in other.iter_pos() {
+                    for &pos in &new_positions {
+                        if pos == other_pos {
+                            return true;
+                        }
+                        let delta = pos - other_pos;
+                        touches |= delta.x.abs() <= 1 && delta.y.abs() <= 1;
+                    }
+                }
+                !touches
+            })
+    }
+
+    /// Return how many letters of the positioned token conflict with the letters already in the
+    /// grid, where `check_letters` only tells whether there is any. Nothing scores grids by this
+    /// count yet: the insertion path still rejects any conflict.
+    pub fn check_letters_soft(&self, token: &Token, positioned: PositionedToken) -> usize {
+        positioned
+            .iter(token)
+            .filter(|&(xy, new_letter)| match self.letter_by_pos.get(&xy) {
+                None => false,
+                Some(&current_letter) => current_letter != new_letter,
+            })
+            .count()
+    }
+}
+
+impl FillConfig {
+    /// Restrict the random fill to `max_distinct` letters, randomly chosen
+    pub fn cap_distinct_letters(
+        &mut self,
+        max_distinct: usize,
+        random: &mut impl Rng,
+    ) -> Result<()> {
+        ensure!(
+            max_distinct > 0,
+            "The fill needs at least one distinct letter"
+        );
+
+        let mut decoy_letters = [false; 26];
+        for letter in Letter::all()
+            .collect_vec()
+            .choose_multiple(random, max_distinct)
+        {
+            decoy_letters[*letter as usize] = true;
+        }
+        self.decoy_letters = Some(decoy_letters);
+        Ok(())
+    }
+
+    fn allows(self, letter: Letter) -> bool {
+        match self.decoy_letters {
+            None => true,
+            Some(decoy_letters) => decoy_letters[letter as usize],
+        }
+    }
+
+    /// Warn that `count` random letters had to extend a run beyond `max_letter_run`
+    fn warn_long_runs(self, count: usize) {
+        if let Some(max_letter_run) = self.max_letter_run.filter(|_| count > 0) {
+            log::warn!(
+                "{} random letters extend a run beyond {} letters, since no allowed letter could \
+                 avoid it",
+                count,
+                max_letter_run
+            );
+        }
+    }
+}
+
+impl FillBias {
+    /// The relative chance of drawing a letter that appears `nearby` times around the cell
+    fn weight(self, nearby: u32) -> f64 {
+        match self {
+            FillBias::Neutral => 1.,
+            FillBias::Blend => 1. + FILL_BIAS_STRENGTH * nearby as f64,
+            FillBias::Contrast => 1. / (1. + FILL_BIAS_STRENGTH * nearby as f64),
+        }
+    }
+}
+
+impl FromStr for FillBias {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "neutral" => Ok(FillBias::Neutral),
+            "blend" => Ok(FillBias::Blend),
+            "contrast" => Ok(FillBias::Contrast),
+            _ => Err(anyhow!("Fill bias was not recognized: {}", s)),
+        }
+    }
+}
+
+/// Whether the frame added around a grid counts for its aspect ratio
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameMode {
+    /// The frame is part of the aspect ratio box, leaving less space for the content
+    Inset,
+    /// The frame surrounds the aspect ratio box
+    Outside,
+}
+
+impl FromStr for FrameMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "inset" => Ok(FrameMode::Inset),
+            "outside" => Ok(FrameMode::Outside),
+            _ => Err(anyhow!("FrameMode was not recognized: {}", s)),
+        }
+    }
+}
+
+impl PartialEq for Grid {
+    fn eq(&self, other: &Self) -> bool {
+        self.canonical_layout() == other.canonical_layout()
+    }
+}
+
+impl Eq for Grid {}
+
+impl Hash for Grid {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical_layout().hash(state);
+    }
+}
+
+impl Default for Grid {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl fmt::Display for Grid {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // An empty grid would otherwise print nothing or a rectangle of spaces
+        if self.is_empty() {
+            return f.write_str("(empty grid)");
+        }
+
         let (x_limits, y_limits) = self.space();
 
         for y in y_limits {
@@ -241,3 +887,418 @@ impl fmt::Display for Grid {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::merge_dag::MergeDag;
+    use crate::models::phrase_book::PhraseBook;
+    use crate::models::word::WordId;
+    use crate::tokenize::{tokenize, MergeObjective};
+    use std::convert::TryInto;
+
+    const CONFIG: InsertionConfig = InsertionConfig {
+        allow_diagonal: false,
+        reading_axis: ReadingAxis::RowMajor,
+        max_pivots_per_letter: None,
+        separate_phrases: false,
+        separate_shared_boundaries: false,
+    };
+
+    /// Tokenize the phrases, whose words are separated by spaces
+    fn tokens(phrases: &[&str]) -> (PhraseBook, MergeDag<WordId, Token>) {
+        let phrase_book = PhraseBook::from_phrases(phrases);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        (phrase_book, token_graph)
+    }
+
+    /// Return the token of the given word of the given phrase
+    fn token<'a>(
+        phrase_book: &PhraseBook,
+        token_graph: &'a MergeDag<WordId, Token>,
+        phrase: usize,
+        word: usize,
+    ) -> &'a Token {
+        token_graph
+            .group(phrase_book.phrases()[phrase].words[word])
+            .1
+    }
+
+    fn positioned(token: &Token, direction: Direction, start: XY) -> PositionedToken {
+        let oriented = OrientedToken::orientations(token, true)
+            .into_iter()
+            .find(|oriented| oriented.direction() == direction)
+            .unwrap();
+        PositionedToken::new(oriented, start)
+    }
+
+    #[test]
+    fn empty() {
+        assert!(Grid::new().is_empty());
+        assert_eq!(Grid::new().to_string(), "(empty grid)");
+
+        let grid = Grid::from_letters(&[vec![None, None], vec![None, None]]);
+        assert!(grid.is_empty());
+        assert_eq!(grid.size(), (2, 2));
+        assert_eq!(grid.to_string(), "(empty grid)");
+
+        let grid = Grid::from_letters(&[vec![None, Some(Letter::A)]]);
+        assert!(!grid.is_empty());
+        assert_eq!(grid.to_string(), " A\n");
+    }
+
+    #[test]
+    fn add_frame() {
+        let (phrase_book, token_graph) = tokens(&["HELLO"]);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+        let token = token(&phrase_book, &token_graph, 0, 0);
+
+        let random = &mut rand::thread_rng();
+        let mut grid =
+            Grid::new().enumerate_insertions(&relations, token, CONFIG, random)[0].clone();
+        grid.fill_to_size(6, 3, FillConfig::default(), random)
+            .unwrap();
+        grid.add_frame(2, Some(Letter::X), FillConfig::default(), random);
+
+        assert_eq!(grid.size(), (10, 7));
+        assert_eq!(grid.get(grid.top_left()), Some(Letter::X));
+        assert_eq!(grid.to_string().matches("HELLO").count(), 1);
+    }
+
+    #[test]
+    fn subgrid() {
+        let (phrase_book, token_graph) = tokens(&["HELLO"]);
+        let token = token(&phrase_book, &token_graph, 0, 0);
+
+        let random = &mut rand::thread_rng();
+        let mut grid = Grid::new();
+        grid.insert(token, positioned(token, Direction::Horizontal, XY::ORIGIN));
+        grid.fill_to_size(5, 5, FillConfig::default(), random)
+            .unwrap();
+
+        let subgrid = grid.subgrid(1..=3, 0..=2);
+        assert_eq!(subgrid.size(), (3, 3));
+        assert_eq!(subgrid.top_left(), XY::new(1, 0));
+        assert_eq!(subgrid.num_letters(), 9);
+        assert_eq!(subgrid.to_string().lines().next(), Some("ELL"));
+        assert!(!subgrid.contains_token(token.id));
+        assert!(grid.subgrid(0..=4, 0..=0).contains_token(token.id));
+    }
+
+    #[test]
+    fn iter_cells() {
+        let letter = |c: char| Some(c.try_into().unwrap());
+        let grid = Grid::from_letters(&[vec![letter('A'), letter('B')], vec![letter('C')]]);
+        let letters = grid.to_letters();
+
+        let cells = grid.iter_cells().collect_vec();
+        assert_eq!(cells.len(), letters.len() * letters[0].len());
+        assert_eq!(cells[1], (XY::new(1, 0), letter('B')));
+        assert_eq!(cells[3], (XY::new(1, 1), None));
+        for (pos, letter) in cells {
+            assert_eq!(letters[pos.y as usize][pos.x as usize], letter);
+        }
+    }
+
+    #[test]
+    fn check_letters_soft() {
+        let (phrase_book, token_graph) = tokens(&["HELLO", "HELP", "JELLY"]);
+        let token = |i: usize| token(&phrase_book, &token_graph, i, 0);
+        let at_origin = |token: &Token| positioned(token, Direction::Horizontal, XY::ORIGIN);
+
+        let mut grid = Grid::new();
+        grid.insert(token(0), at_origin(token(0)));
+
+        assert_eq!(grid.check_letters_soft(token(0), at_origin(token(0))), 0);
+        assert_eq!(grid.check_letters_soft(token(1), at_origin(token(1))), 1);
+        assert_eq!(grid.check_letters_soft(token(2), at_origin(token(2))), 2);
+    }
+
+    #[test]
+    fn shift() {
+        let (phrase_book, token_graph) = tokens(&["HELLO"]);
+        let token = token(&phrase_book, &token_graph, 0, 0);
+
+        let mut grid = Grid::new();
+        grid.insert(
+            token,
+            positioned(token, Direction::Horizontal, XY::new(3, 2)),
+        );
+        grid.shift(XY::new(-3, -2));
+
+        assert_eq!(grid.top_left(), XY::ORIGIN);
+        assert_eq!(grid.get(XY::ORIGIN), Some(Letter::H));
+        assert_eq!(
+            grid.positions_for_token(token.id).unwrap().next(),
+            Some(XY::ORIGIN)
+        );
+    }
+
+    #[test]
+    fn shift_empty() {
+        let mut grid = Grid::new();
+        grid.shift(XY::new(1, 1));
+        assert!(grid.is_empty());
+    }
+
+    #[test]
+    fn token_offset() {
+        let (phrase_book, token_graph) = tokens(&["HELLO WORLD"]);
+        let hello = token(&phrase_book, &token_graph, 0, 0);
+        let world = token(&phrase_book, &token_graph, 0, 1);
+
+        // "WORLD" goes down through the last "O" of "HELLO", so it sticks out above it
+        let mut grid = Grid::new();
+        grid.insert(hello, positioned(hello, Direction::Horizontal, XY::ORIGIN));
+        grid.insert(
+            world,
+            positioned(world, Direction::Vertical, XY::new(4, -1)),
+        );
+
+        let letters = grid.to_letters();
+        let hello_offset = grid.token_offset(hello.id).unwrap();
+        assert_eq!(hello_offset, XY::new(0, 1));
+        assert_eq!(letters[1][0], Some(Letter::H));
+        let world_offset = grid.token_offset(world.id).unwrap();
+        assert_eq!(world_offset, XY::new(4, 0));
+        assert_eq!(letters[0][4], Some(Letter::W));
+    }
+
+    #[test]
+    fn scan_line() {
+        let (phrase_book, token_graph) = tokens(&["HELLO"]);
+        let token = token(&phrase_book, &token_graph, 0, 0);
+
+        let mut grid = Grid::new();
+        grid.insert(token, positioned(token, Direction::Diagonal, XY::ORIGIN));
+
+        let row = grid.scan_line(XY::new(1, 1), Direction::Horizontal.as_xy());
+        assert_eq!(
+            row.map(|(_, letter)| letter).collect_vec(),
+            vec![Some(Letter::E), None, None, None]
+        );
+
+        let diagonal = grid.scan_line(XY::ORIGIN, Direction::Diagonal.as_xy());
+        assert_eq!(
+            diagonal.map(|(pos, _)| pos).collect_vec(),
+            (0..5).map(|i| XY::new(i, i)).collect_vec()
+        );
+    }
+
+    #[test]
+    fn max_letter_run() {
+        let (phrase_book, token_graph) = tokens(&["AA"]);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+        let token = token(&phrase_book, &token_graph, 0, 0);
+
+        let random = &mut rand::thread_rng();
+        let mut grid =
+            Grid::new().enumerate_insertions(&relations, token, CONFIG, random)[0].clone();
+        let config = FillConfig {
+            max_letter_run: Some(2),
+            ..FillConfig::default()
+        };
+        grid.fill_to_size(40, 40, config, random).unwrap();
+
+        let letters = grid.to_letters();
+        let rows = letters.iter().cloned().collect_vec();
+        let columns = (0..40)
+            .map(|x| letters.iter().map(|row| row[x]).collect_vec())
+            .collect_vec();
+        for line in rows.iter().chain(&columns) {
+            for (_, run) in &line.iter().group_by(|&&letter| letter) {
+                assert!(run.count() <= 2);
+            }
+        }
+    }
+
+    #[test]
+    fn max_letter_run_fallback() {
+        let (phrase_book, token_graph) = tokens(&["AA"]);
+        let token = token(&phrase_book, &token_graph, 0, 0);
+
+        let random = &mut rand::thread_rng();
+        let mut grid = Grid::new();
+        grid.insert(token, positioned(token, Direction::Horizontal, XY::ORIGIN));
+        let mut decoy_letters = [false; 26];
+        decoy_letters[Letter::A as usize] = true;
+        let config = FillConfig {
+            max_letter_run: Some(2),
+            decoy_letters: Some(decoy_letters),
+            ..FillConfig::default()
+        };
+
+        // Only "A" is allowed, so the run cannot be avoided
+        let no_letters = BTreeMap::new();
+        assert!(!grid.fill_cell(XY::new(2, 0), None, config, &no_letters, random));
+        assert_eq!(grid.get(XY::new(2, 0)), Some(Letter::A));
+        assert!(grid.fill_cell(XY::new(0, 2), None, config, &no_letters, random));
+    }
+
+    #[test]
+    fn fill_bias() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let (phrase_book, token_graph) = tokens(&["HELLO"]);
+        let token = token(&phrase_book, &token_graph, 0, 0);
+
+        // Count the random letters around the word that are one of its letters
+        let reused_letters = |bias| {
+            let random = &mut SmallRng::seed_from_u64(17);
+            let mut grid = Grid::new();
+            grid.insert(token, positioned(token, Direction::Horizontal, XY::ORIGIN));
+            let config = FillConfig {
+                bias,
+                ..FillConfig::default()
+            };
+            grid.fill_to_size(9, 5, config, random).unwrap();
+
+            grid.iter_cells()
+                .filter(|&(pos, letter)| {
+                    pos.y != 0 && token.text().letters().contains(&letter.unwrap())
+                })
+                .count()
+        };
+
+        let neutral = reused_letters(FillBias::Neutral);
+        assert!(reused_letters(FillBias::Blend) > neutral);
+        assert!(reused_letters(FillBias::Contrast) < neutral);
+    }
+
+    #[test]
+    fn cap_distinct_letters() {
+        let (phrase_book, token_graph) = tokens(&["HELLO"]);
+        let token = token(&phrase_book, &token_graph, 0, 0);
+
+        let random = &mut rand::thread_rng();
+        let mut config = FillConfig {
+            max_letter_run: Some(2),
+            ..FillConfig::default()
+        };
+        assert!(config.cap_distinct_letters(0, random).is_err());
+        config.cap_distinct_letters(3, random).unwrap();
+
+        let mut grid = Grid::new();
+        grid.insert(token, positioned(token, Direction::Horizontal, XY::ORIGIN));
+        grid.fill_to_size(12, 8, config, random).unwrap();
+        grid.add_frame(1, None, config, random);
+
+        let decoys = grid
+            .iter_cells()
+            .filter(|&(pos, _)| pos.y != 0 || !(0..5).contains(&pos.x))
+            .map(|(_, letter)| letter.unwrap())
+            .collect::<BTreeSet<_>>();
+        assert!(decoys.len() <= 3);
+    }
+
+    #[test]
+    fn confusable_reads() {
+        let (phrase_book, token_graph) = tokens(&["HI", "TO", "HIT"]);
+        let real = (0..3)
+            .map(|i| token(&phrase_book, &token_graph, i, 0))
+            .collect_vec();
+        let mut grid = Grid::new();
+        let mut place = |i: usize, direction: Direction, start: XY| {
+            grid.insert(real[i], positioned(real[i], direction, start));
+        };
+
+        // "HI" and "TO" side by side in the first row, which also reads "HIT", and "HIT" down the
+        // last column, which also reads "HI" but within a single token:
+        //   HITOH
+        //   ....I
+        //   ....T
+        place(0, Direction::Horizontal, XY::new(0, 0));
+        place(1, Direction::Horizontal, XY::new(2, 0));
+        place(2, Direction::Vertical, XY::new(4, 0));
+
+        let texts = real.iter().map(|token| token.text().clone()).collect_vec();
+        let reads = grid
+            .confusable_reads(&texts)
+            .into_iter()
+            .map(|(start, direction, text)| (start, direction, text.to_string()))
+            .collect_vec();
+        assert_eq!(
+            reads,
+            vec![(XY::new(0, 0), Direction::Horizontal, "HIT".to_owned())]
+        );
+    }
+
+    #[test]
+    fn separate_phrases() {
+        let (phrase_book, token_graph) = tokens(&["CAT", "DOG"]);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+        let token = |i: usize| token(&phrase_book, &token_graph, i, 0);
+
+        let random = &mut rand::thread_rng();
+        let grid =
+            Grid::new().enumerate_insertions(&relations, token(0), CONFIG, random)[0].clone();
+        let mut touches = |config| {
+            grid.enumerate_insertions(&relations, token(1), config, random)
+                .iter()
+                .any(|grid| {
+                    let cat = grid.positions_for_token(token(0).id).unwrap().collect_vec();
+                    grid.positions_for_token(token(1).id).unwrap().any(|dog| {
+                        cat.iter().any(|&cat| {
+                            let delta = dog - cat;
+                            delta.x.abs() <= 1 && delta.y.abs() <= 1
+                        })
+                    })
+                })
+        };
+
+        assert!(touches(CONFIG));
+        assert!(!touches(InsertionConfig {
+            separate_phrases: true,
+            ..CONFIG
+        }));
+    }
+
+    #[test]
+    fn forbidden_cells() {
+        let (phrase_book, token_graph) = tokens(&["HELLO WORLD"]);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+
+        // Reserve a cell in the first row and one in the first column
+        let forbidden = [XY::new(2, 0), XY::new(0, 2)];
+        let random = &mut rand::thread_rng();
+        let mut grid = Grid::with_forbidden_cells(forbidden);
+        for word in 0..2 {
+            let token = token(&phrase_book, &token_graph, 0, word);
+            let insertions = grid.enumerate_insertions(&relations, token, CONFIG, random);
+            assert!(insertions
+                .iter()
+                .all(|grid| { forbidden.iter().all(|&pos| grid.get(pos).is_none()) }));
+            grid = insertions[0].clone();
+        }
+
+        let (width, height) = grid.size();
+        grid.fill_to_size(width + 4, height + 4, FillConfig::default(), random)
+            .unwrap();
+        grid.add_frame(1, None, FillConfig::default(), random);
+        for (pos, letter) in grid.iter_cells() {
+            assert_eq!(letter.is_none(), forbidden.contains(&pos));
+        }
+    }
+
+    #[test]
+    fn max_pivots_per_letter() {
+        let (phrase_book, token_graph) = tokens(&["EEEEEEEEEE", "HE"]);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+        let token = |i: usize| token(&phrase_book, &token_graph, i, 0);
+
+        let mut config = CONFIG;
+        let random = &mut rand::thread_rng();
+        let grid =
+            Grid::new().enumerate_insertions(&relations, token(0), config, random)[0].clone();
+
+        let all_insertions = grid.enumerate_insertions(&relations, token(1), config, random);
+        config.max_pivots_per_letter = Some(2);
+        let sampled_insertions = grid.enumerate_insertions(&relations, token(1), config, random);
+
+        // The pivot insertions are bound by 2 orientations of 2 pivots, plus at most 2 insertions
+        // for each one of the 3 scan directions in each orientation
+        assert!(all_insertions.len() > 2 * 2 + 2 * 3 * 2);
+        assert!(sampled_insertions.len() <= 2 * 2 + 2 * 3 * 2);
+    }
+}
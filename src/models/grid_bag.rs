@@ -1,10 +1,15 @@
-use crate::models::grid::Grid;
-use crate::models::token::Token;
+use crate::models::aspect_ratio::AspectRatio;
+use crate::models::grid::{Grid, InsertionConfig};
+use crate::models::io::GridBagEntry;
+use crate::models::token::{Token, TokenId};
 use crate::models::token_relations::TokenRelations;
-use crate::AspectRatio;
+use anyhow::{anyhow, Error};
 use itertools::Itertools;
-use rand::prelude::SliceRandom;
+use ordered_float::OrderedFloat;
+use rand::rngs::SmallRng;
+use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
+use std::str::FromStr;
 use std::{fmt, mem};
 
 #[derive(Debug, Clone)]
@@ -13,36 +18,132 @@ pub struct GridBag {
     grids: Vec<Grid>,
     /// The target aspect ratio of this grid
     target_aspect: AspectRatio,
+    /// How much the distance between tokens that appear together in phrases weighs
+    proximity_weight: f64,
+    co_occurrences: Vec<(TokenId, TokenId, u16)>,
+    /// How much each change of direction between consecutive tokens of a phrase weighs
+    direction_change_penalty: f64,
+    successions: Vec<(TokenId, TokenId, u16)>,
+    weight: GridWeight,
+}
+
+/// Determine which grids are deemed the most interesting
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum GridWeight {
+    /// Prefer the grids with the smallest area once covered by the target aspect ratio, then the
+    /// ones with fewer letters
+    #[default]
+    Area,
+    /// Prefer the grids that need the fewest random letters to cover the target aspect ratio,
+    /// which are the cells lit for nothing on e-ink or LED clocks
+    MinInk,
 }
 
 impl GridBag {
-    pub fn new(target_aspect: AspectRatio) -> Self {
+    /// Create a bag that starts with a single grid, that may already have some tokens
+    pub fn new_from(
+        grid: Grid,
+        target_aspect: AspectRatio,
+        proximity_weight: f64,
+        direction_change_penalty: f64,
+        weight: GridWeight,
+        relations: &TokenRelations,
+    ) -> Self {
         GridBag {
             tokens: vec![],
-            grids: vec![Grid::new()],
+            grids: vec![grid],
             target_aspect,
+            proximity_weight,
+            co_occurrences: relations.co_occurrences().to_vec(),
+            direction_change_penalty,
+            successions: relations.successions().to_vec(),
+            weight,
         }
     }
 
-    pub fn insert(&mut self, relations: &TokenRelations, token: &Token, allow_diagonal: bool) {
-        self.grids = self
+    /// Insert the token in all possible ways in all grids, keeping only the grids that are at most
+    /// `max_dimension` wide and high.
+    ///
+    /// If no grid can accept the token, the bag is left unchanged and `false` is returned.
+    pub fn insert(
+        &mut self,
+        relations: &TokenRelations,
+        token: &Token,
+        config: InsertionConfig,
+        max_dimension: Option<i16>,
+        random: &mut impl Rng,
+    ) -> bool {
+        // Each grid gets its own random generator, since they are processed in parallel
+        let seeds = (0..self.grids.len()).map(|_| random.gen()).collect_vec();
+        let grids: Vec<_> = self
             .grids
             .par_iter()
-            .flat_map(|grid| grid.enumerate_insertions(relations, token, allow_diagonal))
+            .zip(seeds)
+            .flat_map(|(grid, seed)| {
+                let mut random = SmallRng::seed_from_u64(seed);
+                grid.enumerate_insertions(relations, token, config, &mut random)
+            })
+            .filter(|grid| match max_dimension {
+                None => true,
+                Some(max_dimension) => {
+                    let (width, height) = grid.size();
+                    width <= max_dimension && height <= max_dimension
+                }
+            })
             .collect();
 
+        if grids.is_empty() {
+            return false;
+        }
+
+        self.grids = grids;
+        self.tokens.push(token.clone());
+        true
+    }
+
+    /// Return the grids resulting from all the valid insertions of the token in the best grid, from
+    /// the most to the least interesting. This allows placing the tokens one at a time, picking one
+    /// of these grids with [`GridBag::insert_single()`].
+    pub fn ranked_insertions(
+        &self,
+        relations: &TokenRelations,
+        token: &Token,
+        config: InsertionConfig,
+        random: &mut impl Rng,
+    ) -> Vec<Grid> {
+        let mut grids = self
+            .best_grid()
+            .enumerate_insertions(relations, token, config, random);
+        grids.sort_by_cached_key(|grid| self.weight_for_grid(grid));
+        grids
+    }
+
+    /// Replace all grids by the given one, in which the token was inserted, like one returned by
+    /// [`GridBag::ranked_insertions()`].
+    ///
+    /// # Panics
+    /// It will panic if the token is not in the grid
+    pub fn insert_single(&mut self, token: &Token, grid: Grid) {
+        assert!(grid.contains_token(token.id));
+        self.grids = vec![grid];
         self.tokens.push(token.clone());
     }
 
-    pub fn trim(&mut self, max_size: usize) {
+    pub fn trim(&mut self, max_size: usize, random: &mut impl Rng) {
         if self.grids.len() > max_size {
             let initial_size = self.grids.len();
 
-            let mut grids = mem::take(&mut self.grids);
-            grids.shuffle(&mut rand::thread_rng());
-            grids.par_sort_unstable_by_key(|grid| self.weight_for_grid(grid));
+            // Break ties with a random priority per grid, so that trimming is reproducible for a
+            // given random generator
+            let mut grids = mem::take(&mut self.grids)
+                .into_iter()
+                .map(|grid| (random.gen::<u64>(), grid))
+                .collect_vec();
+            grids.par_sort_unstable_by_key(|(priority, grid)| {
+                (self.weight_for_grid(grid), *priority)
+            });
             grids.truncate(max_size);
-            self.grids = grids;
+            self.grids = grids.into_iter().map(|(_, grid)| grid).collect();
 
             let final_size = self.grids.len();
 
@@ -61,15 +162,69 @@ impl GridBag {
             .unwrap()
     }
 
+    /// Describe each grid with the components of its weight, from the most to the least
+    /// interesting
+    pub fn entries(&self) -> Vec<GridBagEntry> {
+        self.grids
+            .iter()
+            .sorted_by_key(|grid| self.weight_for_grid(grid))
+            .map(|grid| {
+                let (width, height) = grid.size();
+                let (aspect_area, num_letters, _) = self.weight_components(grid);
+                GridBagEntry {
+                    minimal_grid: grid.to_letters(),
+                    width,
+                    height,
+                    num_letters,
+                    aspect_area,
+                }
+            })
+            .collect()
+    }
+
     /// A grid with lower weight is deemed more interesting
-    fn weight_for_grid(&self, grid: &Grid) -> (i16, i16, i16) {
+    fn weight_for_grid(&self, grid: &Grid) -> (i16, i16, OrderedFloat<f64>) {
+        let (aspect_area, num_letters, soft_weight) = self.weight_components(grid);
+        match self.weight {
+            GridWeight::Area => (aspect_area, num_letters, soft_weight),
+            GridWeight::MinInk => (aspect_area - num_letters, aspect_area, soft_weight),
+        }
+    }
+
+    /// Return the area of the grid once covered by the target aspect ratio, its number of letters
+    /// and the soft weight that breaks ties
+    fn weight_components(&self, grid: &Grid) -> (i16, i16, OrderedFloat<f64>) {
         let (width, height) = grid.size();
         let area = width * height;
 
         let (aspect_width, aspect_height) = self.target_aspect.cover(width, height);
         let aspect_area = aspect_width * aspect_height;
 
-        (aspect_area, grid.num_letters(), area)
+        // The distance between related tokens and the turns in the phrases are soft objectives,
+        // only compared with the area
+        let mut soft_weight = area as f64;
+        if self.proximity_weight != 0. {
+            soft_weight +=
+                self.proximity_weight * grid.co_occurrence_distance(&self.co_occurrences);
+        }
+        if self.direction_change_penalty != 0. {
+            soft_weight +=
+                self.direction_change_penalty * grid.direction_changes(&self.successions) as f64;
+        }
+
+        (aspect_area, grid.num_letters(), OrderedFloat(soft_weight))
+    }
+}
+
+impl FromStr for GridWeight {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "area" => Ok(GridWeight::Area),
+            "min-ink" => Ok(GridWeight::MinInk),
+            _ => Err(anyhow!("Grid weight was not recognized: {}", s)),
+        }
     }
 }
 
@@ -78,3 +233,107 @@ impl fmt::Display for GridBag {
         write!(f, "{}", self.tokens.iter().format("\n"))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::phrase_book::PhraseBook;
+    use crate::models::position_restriction::ReadingAxis;
+    use crate::tokenize::{tokenize, MergeObjective};
+
+    fn test_insertion_config() -> InsertionConfig {
+        InsertionConfig {
+            allow_diagonal: false,
+            reading_axis: ReadingAxis::RowMajor,
+            max_pivots_per_letter: None,
+            separate_phrases: false,
+            separate_shared_boundaries: false,
+        }
+    }
+
+    /// Return an empty bag targeting 16:9, with no proximity weight nor direction change penalty
+    fn test_grid_bag(weight: GridWeight, relations: &TokenRelations) -> GridBag {
+        GridBag::new_from(
+            Grid::new(),
+            "16:9".parse().unwrap(),
+            0.,
+            0.,
+            weight,
+            relations,
+        )
+    }
+
+    #[test]
+    fn seeded_trim() {
+        let phrase_book = PhraseBook::from_phrases(&["IT IS ONE", "IT IS TWO"]);
+        let graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let relations = TokenRelations::new(&graph, phrase_book.phrases());
+        let config = test_insertion_config();
+
+        let survivors = |seed| {
+            let random = &mut SmallRng::seed_from_u64(seed);
+            let mut grid_bag = test_grid_bag(GridWeight::Area, &relations);
+            for (_, token) in graph.groups() {
+                assert!(grid_bag.insert(&relations, token, config, None, random));
+            }
+            assert!(grid_bag.grids().len() > 10);
+
+            grid_bag.trim(10, random);
+            grid_bag.grids().iter().map(Grid::to_string).collect_vec()
+        };
+
+        assert_eq!(survivors(17), survivors(17));
+    }
+
+    #[test]
+    fn ranked_insertions() {
+        let phrase_book = PhraseBook::from_phrases(&["IT IS"]);
+        let graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let relations = TokenRelations::new(&graph, phrase_book.phrases());
+        let config = test_insertion_config();
+        let random = &mut SmallRng::seed_from_u64(17);
+        let mut tokens = graph.groups().map(|(_, token)| token);
+        let it = tokens.next().unwrap();
+        let is = tokens.next().unwrap();
+
+        let mut grid_bag = test_grid_bag(GridWeight::Area, &relations);
+        let candidates = grid_bag.ranked_insertions(&relations, it, config, random);
+        grid_bag.insert_single(it, candidates[0].clone());
+
+        let candidates = grid_bag.ranked_insertions(&relations, is, config, random);
+        assert!(candidates.len() > 1);
+        assert!(candidates
+            .iter()
+            .tuple_windows()
+            .all(|(a, b)| grid_bag.weight_for_grid(a) <= grid_bag.weight_for_grid(b)));
+
+        grid_bag.insert_single(is, candidates[0].clone());
+        assert_eq!(grid_bag.grids().len(), 1);
+        assert!(grid_bag.best_grid().contains_token(it.id));
+        assert!(grid_bag.best_grid().contains_token(is.id));
+    }
+
+    #[test]
+    fn min_ink() {
+        let phrase_book = PhraseBook::from_phrases(&["IT IS ONE", "IT IS TWO"]);
+        let graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let relations = TokenRelations::new(&graph, phrase_book.phrases());
+        let config = test_insertion_config();
+
+        // Both bags hold the same grids, since the weight only matters when trimming
+        let decoys = |weight| {
+            let random = &mut SmallRng::seed_from_u64(17);
+            let mut grid_bag = test_grid_bag(weight, &relations);
+            for (_, token) in graph.groups() {
+                assert!(grid_bag.insert(&relations, token, config, None, random));
+            }
+
+            let best_grid = grid_bag.best_grid();
+            let (width, height) = best_grid.size();
+            let (aspect_width, aspect_height) = grid_bag.target_aspect.cover(width, height);
+            aspect_width * aspect_height - best_grid.num_letters()
+        };
+
+        assert!(decoys(GridWeight::MinInk) <= decoys(GridWeight::Area));
+    }
+}
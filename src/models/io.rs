@@ -1,38 +1,478 @@
+use crate::models::language::Language;
 use crate::models::letter::Letter;
 use crate::models::phrase::TimePhrase;
 use crate::models::text::Text;
+use anyhow::{anyhow, ensure, Error, Result};
+use itertools::Itertools;
+use rand::seq::index;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use std::str::FromStr;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TimePhrasesOutput {
     pub phrases: Vec<TimePhrase>,
 }
 
+/// The format of the time phrases file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhrasesFormat {
+    /// JSON, represented by [`TimePhrasesOutput`]
+    Json,
+    /// One phrase per line, with tab-separated language, hours, minutes and phrase
+    Tsv,
+}
+
+impl TimePhrasesOutput {
+    /// Encode the phrases in the given format
+    pub fn encode(&self, format: PhrasesFormat) -> serde_json::Result<String> {
+        match format {
+            PhrasesFormat::Json => serde_json::to_string_pretty(self),
+            PhrasesFormat::Tsv => Ok(self
+                .phrases
+                .iter()
+                .map(|phrase| {
+                    format!(
+                        "{}\t{}\t{}\t{}\n",
+                        format!("{:?}", phrase.language).to_lowercase(),
+                        phrase.time.hours(),
+                        phrase.time.minutes(),
+                        phrase.texts.iter().format(" ")
+                    )
+                })
+                .collect()),
+        }
+    }
+}
+
+impl FromStr for PhrasesFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(PhrasesFormat::Json),
+            "tsv" => Ok(PhrasesFormat::Tsv),
+            _ => Err(anyhow!("Phrases format was not recognized: {}", s)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GridInput {
     pub phrases: Vec<GridInputPhrase>,
+    /// The cells `(x, y)` that must never hold a letter, for example for a logo. They are in the
+    /// coordinates in which the grid is built: the first word is tried at the origin and the grid
+    /// can grow in any direction from there. The grid is filled around them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forbidden_cells: Vec<(i16, i16)>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GridInputPhrase {
     pub texts: Vec<Text>,
+    /// The language of the phrase, present when the phrases come from `TimePhrasesOutput`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<Language>,
+    /// Whether to place the phrase in the grid. Disabled phrases are skipped, as if they were not
+    /// in the input, so the phrases of [`GridOutput`] only include the enabled ones and their
+    /// indexes shift accordingly.
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
 }
 
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// The current version of the [`GridOutput`] format
+pub const GRID_OUTPUT_VERSION: u32 = 3;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GridOutput {
+    /// The version of the format. Files written before it was introduced read as version 0.
+    #[serde(default)]
+    pub version: u32,
     pub minimal_grid: Vec<Vec<Option<Letter>>>,
     pub grid: Vec<Vec<Letter>>,
+    /// The cells of `grid` that are not random, like a frame of placeholder letters or the
+    /// placeholders of the forbidden cells, in the same [`CoordOrder`] as the phrases. A refill
+    /// keeps their letters.
+    #[serde(default)]
+    pub fixed_cells: Vec<(i16, i16)>,
+    /// The phrases, in the order determined by [`PhraseOrder`]. Consumers that pair them with
+    /// other phrase lists, like the lyrics, must use the same order.
+    pub phrases: Vec<GridOutputPhrase>,
+}
+
+/// The layout part of [`GridOutput`], without the random fill. The phrase positions are relative
+/// to the minimal grid.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MinimalGridOutput {
+    pub minimal_grid: Vec<Vec<Option<Letter>>>,
     pub phrases: Vec<GridOutputPhrase>,
 }
 
+/// The filled part of [`GridOutput`], to be recombined with a [`MinimalGridOutput`]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilledGridOutput {
+    pub grid: Vec<Vec<Letter>>,
+    /// See [`GridOutput::fixed_cells`]
+    #[serde(default)]
+    pub fixed_cells: Vec<(i16, i16)>,
+    /// The position `(x, y)` of the top-left cell of the minimal grid in this grid, in the same
+    /// [`CoordOrder`] as the phrases
+    pub minimal_offset: (i16, i16),
+}
+
+/// Describe one of the grids considered when building the final one, with the components used to
+/// rank them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GridBagEntry {
+    pub minimal_grid: Vec<Vec<Option<Letter>>>,
+    pub width: i16,
+    pub height: i16,
+    pub num_letters: i16,
+    /// The area of the smallest grid with the target aspect ratio that covers this one
+    pub aspect_area: i16,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GridOutputPhrase {
     pub words: Vec<GridOutputWord>,
 }
 
+/// Determine the order of the phrases in [`GridOutput`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhraseOrder {
+    /// Same order as the input
+    Input,
+    /// Sorted by the top-most, then left-most, letter of each phrase
+    Position,
+}
+
+/// Determine the order of the coordinates in [`GridOutputWord::letters`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CoordOrder {
+    /// `(x, y)`, with the origin in the top-left corner. This is the order the other commands read
+    Xy,
+    /// `(row, column)`, that is, `(y, x)`
+    RowCol,
+}
+
+impl CoordOrder {
+    /// Convert a position `(x, y)` to this order
+    pub fn apply(self, (x, y): (i16, i16)) -> (i16, i16) {
+        match self {
+            CoordOrder::Xy => (x, y),
+            CoordOrder::RowCol => (y, x),
+        }
+    }
+}
+
+impl GridOutput {
+    /// Load a grid output, recombining it from a separate minimal grid file when given
+    pub fn load(path: &Path, minimal_path: Option<&Path>) -> Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        let grid: GridOutput = match minimal_path {
+            None => serde_json::from_str(&contents)?,
+            Some(minimal_path) => {
+                let minimal = serde_json::from_str(&fs::read_to_string(minimal_path)?)?;
+                GridOutput::from_parts(minimal, serde_json::from_str(&contents)?)
+            }
+        };
+        grid.migrate()
+    }
+
+    /// Upgrade a grid read from an older version of the format to the current one
+    pub fn migrate(mut self) -> Result<Self> {
+        ensure!(
+            self.version <= GRID_OUTPUT_VERSION,
+            "Grid output version {} is newer than the supported version {}",
+            self.version,
+            GRID_OUTPUT_VERSION
+        );
+
+        ensure!(
+            is_rectangular(&self.grid),
+            "The grid rows have different lengths"
+        );
+        ensure!(
+            is_rectangular(&self.minimal_grid),
+            "The minimal grid rows have different lengths"
+        );
+        let width = self.grid.first().map_or(0, Vec::len);
+        let minimal_width = self.minimal_grid.first().map_or(0, Vec::len);
+        ensure!(
+            self.grid.len() >= self.minimal_grid.len() && width >= minimal_width,
+            "The grid is smaller than its minimal grid"
+        );
+
+        // Version 1 only introduced the version field itself
+        if self.version == 0 {
+            self.version = 1;
+        }
+
+        // Version 2 added the text of each word, which can be read off the grid. Files split with
+        // `--minimal-output` have no version, so also check for missing texts in newer versions.
+        for word in self.phrases.iter_mut().flat_map(|phrase| &mut phrase.words) {
+            if word.text.is_empty() {
+                word.text = word.read(&self.grid)?;
+            }
+        }
+
+        // Version 3 added the fixed cells. Older files did not record them, so a refill replaces
+        // their frame with random letters.
+        self.version = GRID_OUTPUT_VERSION;
+
+        Ok(self)
+    }
+
+    /// Write the letter positions of the phrases in the given order, assuming they are currently
+    /// in `(x, y)` order
+    pub fn with_coord_order(mut self, order: CoordOrder) -> Self {
+        for word in self.phrases.iter_mut().flat_map(|phrase| &mut phrase.words) {
+            for letter in &mut word.letters {
+                *letter = order.apply(*letter);
+            }
+        }
+        for cell in &mut self.fixed_cells {
+            *cell = order.apply(*cell);
+        }
+        self
+    }
+
+    /// Split into the layout and the filled grid. `minimal_offset` is the position of the minimal
+    /// grid in the filled one.
+    pub fn split(self, minimal_offset: (i16, i16)) -> (MinimalGridOutput, FilledGridOutput) {
+        let (dx, dy) = minimal_offset;
+        let minimal = MinimalGridOutput {
+            minimal_grid: self.minimal_grid,
+            phrases: shift_phrases(self.phrases, -dx, -dy),
+        };
+        let filled = FilledGridOutput {
+            grid: self.grid,
+            fixed_cells: self.fixed_cells,
+            minimal_offset,
+        };
+        (minimal, filled)
+    }
+
+    /// The reverse of [`GridOutput::split()`]
+    pub fn from_parts(minimal: MinimalGridOutput, filled: FilledGridOutput) -> Self {
+        let (dx, dy) = filled.minimal_offset;
+        GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: minimal.minimal_grid,
+            grid: filled.grid,
+            fixed_cells: filled.fixed_cells,
+            phrases: shift_phrases(minimal.phrases, dx, dy),
+        }
+    }
+
+    /// Return a hash of the minimal grid and of the positions of the phrases in it, ignoring the
+    /// random fill around it. Two outputs with the same layout have the same key.
+    pub fn canonical_key(&self) -> u64 {
+        let letters = || {
+            self.phrases
+                .iter()
+                .flat_map(|phrase| &phrase.words)
+                .flat_map(|word| word.letters.iter().copied())
+        };
+        let min_x = letters().map(|(x, _)| x).min().unwrap_or(0);
+        let min_y = letters().map(|(_, y)| y).min().unwrap_or(0);
+
+        let mut hasher = DefaultHasher::new();
+        self.minimal_grid.hash(&mut hasher);
+        for phrase in &self.phrases {
+            phrase.words.len().hash(&mut hasher);
+            for word in &phrase.words {
+                word.letters.len().hash(&mut hasher);
+                for &(x, y) in &word.letters {
+                    (x - min_x, y - min_y).hash(&mut hasher);
+                }
+            }
+        }
+        hasher.finish()
+    }
+}
+
+/// Metrics of a built grid, for dashboards and comparisons, represented as JSON
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct GridStats {
+    /// The size of the filled grid
+    pub width: i16,
+    pub height: i16,
+    /// The number of cells used by the words
+    pub num_letters: usize,
+    /// The number of cells shared by more than one word
+    pub crossing_count: usize,
+    /// The fraction of the words that are placed diagonally
+    pub diagonal_fraction: f64,
+    /// The number of cells not used by any word, filled with random letters
+    pub decoy_count: usize,
+    /// The fraction of the cells used by the words
+    pub packing_density: f64,
+    /// The number of groups of cells used by the words, where the cells of each group touch each
+    /// other, even diagonally
+    pub connected_components: usize,
+}
+
+impl GridOutput {
+    /// Compute the metrics of this grid. Each word placement is only counted once, even if it
+    /// appears in many phrases.
+    pub fn stats(&self) -> GridStats {
+        let height = self.grid.len();
+        let width = self.grid.first().map_or(0, Vec::len);
+        let words: BTreeSet<&[(i16, i16)]> = self
+            .phrases
+            .iter()
+            .flat_map(|phrase| &phrase.words)
+            .map(|word| word.letters.as_slice())
+            .collect();
+
+        let mut words_by_cell: BTreeMap<(i16, i16), usize> = BTreeMap::new();
+        for &cell in words.iter().copied().flatten() {
+            *words_by_cell.entry(cell).or_default() += 1;
+        }
+        let num_letters = words_by_cell.len();
+        let crossing_count = words_by_cell.values().filter(|&&count| count > 1).count();
+
+        let diagonals = words
+            .iter()
+            .filter(|letters| match letters {
+                [(x1, y1), (x2, y2), ..] => x1 != x2 && y1 != y2,
+                _ => false,
+            })
+            .count();
+        let diagonal_fraction = if words.is_empty() {
+            0.
+        } else {
+            diagonals as f64 / words.len() as f64
+        };
+
+        let area = width * height;
+        let packing_density = if area == 0 {
+            0.
+        } else {
+            num_letters as f64 / area as f64
+        };
+
+        // Flood fill the cells used by the words
+        let mut unvisited: BTreeSet<_> = words_by_cell.into_keys().collect();
+        let mut connected_components = 0;
+        while let Some(&start) = unvisited.iter().next() {
+            unvisited.remove(&start);
+            connected_components += 1;
+            let mut pending = vec![start];
+            while let Some((x, y)) = pending.pop() {
+                for (dx, dy) in (-1..=1).cartesian_product(-1..=1) {
+                    if unvisited.remove(&(x + dx, y + dy)) {
+                        pending.push((x + dx, y + dy));
+                    }
+                }
+            }
+        }
+
+        GridStats {
+            width: width as i16,
+            height: height as i16,
+            num_letters,
+            crossing_count,
+            diagonal_fraction,
+            decoy_count: area - num_letters,
+            packing_density,
+            connected_components,
+        }
+    }
+}
+
+/// Return whether all the rows have the same length
+fn is_rectangular<T>(rows: &[Vec<T>]) -> bool {
+    rows.iter().map(Vec::len).all_equal()
+}
+
+fn shift_phrases(phrases: Vec<GridOutputPhrase>, dx: i16, dy: i16) -> Vec<GridOutputPhrase> {
+    phrases
+        .into_iter()
+        .map(|phrase| GridOutputPhrase {
+            words: phrase
+                .words
+                .into_iter()
+                .map(|word| GridOutputWord {
+                    letters: word
+                        .letters
+                        .into_iter()
+                        .map(|(x, y)| (x + dx, y + dy))
+                        .collect(),
+                    ..word
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+impl GridOutputPhrase {
+    /// Return the top-most, then left-most, letter position as `(y, x)`
+    pub fn first_position(&self) -> Option<(i16, i16)> {
+        self.words
+            .iter()
+            .flat_map(|word| &word.letters)
+            .map(|&(x, y)| (y, x))
+            .min()
+    }
+}
+
+impl FromStr for CoordOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "xy" => Ok(CoordOrder::Xy),
+            "rowcol" => Ok(CoordOrder::RowCol),
+            _ => Err(anyhow!("Coordinate order was not recognized: {}", s)),
+        }
+    }
+}
+
+impl FromStr for PhraseOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "input" => Ok(PhraseOrder::Input),
+            "position" => Ok(PhraseOrder::Position),
+            _ => Err(anyhow!("Phrase order was not recognized: {}", s)),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GridOutputWord {
     pub letters: Vec<(i16, i16)>,
+    /// The text of the word, with one letter per cell in `letters`. This lets renderers draw the
+    /// boundaries between words.
+    #[serde(default)]
+    pub text: String,
+}
+
+impl GridOutputWord {
+    /// Read the letters of this word off the filled grid
+    pub fn read(&self, grid: &[Vec<Letter>]) -> Result<String> {
+        self.letters
+            .iter()
+            .map(|&(x, y)| {
+                grid.get(y as usize)
+                    .and_then(|row| row.get(x as usize))
+                    .map(|letter| letter.as_char())
+                    .ok_or_else(|| anyhow!("Letter position ({}, {}) is outside the grid", x, y))
+            })
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -42,6 +482,153 @@ pub struct LyricsPuzzleInput {
     pub phrases: Vec<LyricsPhrase>,
 }
 
+/// The input files whose examples can be generated
+#[derive(Debug, Clone, Copy)]
+pub enum InputType {
+    Grid,
+    LyricsPuzzle,
+}
+
+impl GridInput {
+    /// Return a minimal valid example
+    pub fn example() -> Self {
+        GridInput {
+            phrases: vec![
+                GridInputPhrase {
+                    texts: texts("IT IS ONE"),
+                    language: None,
+                    enabled: true,
+                },
+                GridInputPhrase {
+                    texts: texts("IT IS TWO"),
+                    language: None,
+                    enabled: true,
+                },
+            ],
+            forbidden_cells: vec![],
+        }
+    }
+
+    /// Return a seed for the random choices derived from the contents of this input, so that
+    /// building the same input always gives the same grid, while different inputs differ. The
+    /// seed is stable for a given build of this program.
+    pub fn content_seed(&self) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        serde_json::to_string(self)
+            .expect("The input can be serialized")
+            .hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /// Drop the disabled phrases and return how many were dropped
+    pub fn retain_enabled(&mut self) -> usize {
+        let num_phrases = self.phrases.len();
+        self.phrases.retain(|phrase| phrase.enabled);
+        num_phrases - self.phrases.len()
+    }
+
+    /// Keep at most `max_phrases` phrases, sampled uniformly, in their original order. Return the
+    /// dropped phrases.
+    pub fn sample_phrases(
+        &mut self,
+        max_phrases: usize,
+        random: &mut impl Rng,
+    ) -> Vec<GridInputPhrase> {
+        if self.phrases.len() <= max_phrases {
+            return vec![];
+        }
+
+        let kept: BTreeSet<_> = index::sample(random, self.phrases.len(), max_phrases)
+            .into_iter()
+            .collect();
+        let (kept, dropped) = std::mem::take(&mut self.phrases)
+            .into_iter()
+            .enumerate()
+            .partition::<Vec<_>, _>(|(i, _)| kept.contains(i));
+        self.phrases = kept.into_iter().map(|(_, phrase)| phrase).collect();
+        dropped.into_iter().map(|(_, phrase)| phrase).collect()
+    }
+
+    /// Split the phrases by their language, in the order in which each language first appears.
+    /// Each part keeps all the forbidden cells.
+    pub fn partition_by_language(self) -> Result<Vec<(Language, GridInput)>> {
+        let mut partitions: Vec<(Language, GridInput)> = vec![];
+        for phrase in self.phrases {
+            let language = phrase.language.ok_or_else(|| {
+                anyhow!(
+                    "Phrase has no language: {}",
+                    phrase.texts.iter().format(" ")
+                )
+            })?;
+            match partitions
+                .iter_mut()
+                .find(|(other, _)| other.tag() == language.tag())
+            {
+                Some((_, input)) => input.phrases.push(phrase),
+                None => partitions.push((
+                    language,
+                    GridInput {
+                        phrases: vec![phrase],
+                        forbidden_cells: self.forbidden_cells.clone(),
+                    },
+                )),
+            }
+        }
+        Ok(partitions)
+    }
+}
+
+impl LyricsPuzzleInput {
+    /// Return a minimal valid example
+    pub fn example() -> Self {
+        LyricsPuzzleInput {
+            video_id: "YOUTUBE_VIDEO_ID".to_owned(),
+            total_duration: 10_000,
+            phrases: vec![
+                LyricsPhrase {
+                    texts: texts("HELLO WORLD"),
+                    start: 1_000,
+                    end: 2_500,
+                },
+                LyricsPhrase {
+                    texts: texts("GOOD MORNING WORLD"),
+                    start: 3_000,
+                    end: 5_000,
+                },
+            ],
+        }
+    }
+}
+
+impl InputType {
+    /// Return a minimal valid example of this input, as pretty JSON
+    pub fn example_json(self) -> serde_json::Result<String> {
+        match self {
+            InputType::Grid => serde_json::to_string_pretty(&GridInput::example()),
+            InputType::LyricsPuzzle => serde_json::to_string_pretty(&LyricsPuzzleInput::example()),
+        }
+    }
+}
+
+impl FromStr for InputType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "grid" => Ok(InputType::Grid),
+            "lyrics-puzzle" => Ok(InputType::LyricsPuzzle),
+            _ => Err(anyhow!("Input type was not recognized: {}", s)),
+        }
+    }
+}
+
+fn texts(phrase: &str) -> Vec<Text> {
+    phrase
+        .split(' ')
+        .map(|word| word.parse().expect("Valid Text"))
+        .collect()
+}
+
 /// Represents each phrase in the lyrics
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LyricsPhrase {
@@ -49,3 +636,283 @@ pub struct LyricsPhrase {
     pub start: i32,
     pub end: i32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::time::Time;
+
+    #[test]
+    fn encode_tsv() {
+        let phrase = |language, hours, minutes, phrase| TimePhrase {
+            language,
+            time: Time::new(hours, minutes),
+            texts: texts(phrase),
+        };
+        let output = TimePhrasesOutput {
+            phrases: vec![
+                phrase(Language::English, 12, 45, "QUARTER TO ONE"),
+                phrase(Language::French, 0, 30, "MINUIT ET DEMIE"),
+            ],
+        };
+
+        assert_eq!(
+            output.encode(PhrasesFormat::Tsv).unwrap(),
+            "english\t12\t45\tQUARTER TO ONE\nfrench\t0\t30\tMINUIT ET DEMIE\n"
+        );
+    }
+
+    #[test]
+    fn partition_by_language() {
+        let json = r#"{"phrases": [
+            {"language": "English", "hours": 1, "minutes": 0, "texts": ["ONE", "O", "CLOCK"]},
+            {"language": "French", "hours": 1, "minutes": 0, "texts": ["UNE", "HEURE"]},
+            {"language": "English", "hours": 2, "minutes": 0, "texts": ["TWO", "O", "CLOCK"]}
+        ]}"#;
+        let grid_input: GridInput = serde_json::from_str(json).unwrap();
+
+        let partitions = grid_input.partition_by_language().unwrap();
+        let summary = partitions
+            .iter()
+            .map(|(language, input)| (language.tag(), input.phrases.len()))
+            .collect_vec();
+        assert_eq!(summary, vec![("English", 2), ("French", 1)]);
+
+        assert!(GridInput::example().partition_by_language().is_err());
+    }
+
+    #[test]
+    fn retain_enabled() {
+        let json = r#"{"phrases": [
+            {"texts": ["IT", "IS", "ONE"]},
+            {"texts": ["IT", "IS", "TWO"], "enabled": false},
+            {"texts": ["IT", "IS", "THREE"], "enabled": true}
+        ]}"#;
+        let mut grid_input: GridInput = serde_json::from_str(json).unwrap();
+
+        assert_eq!(grid_input.retain_enabled(), 1);
+        let phrases = grid_input
+            .phrases
+            .iter()
+            .map(|phrase| phrase.texts.iter().join(" "))
+            .collect_vec();
+        assert_eq!(phrases, ["IT IS ONE", "IT IS THREE"]);
+    }
+
+    #[test]
+    fn sample_phrases() {
+        use rand::rngs::SmallRng;
+        use rand::SeedableRng;
+
+        let mut grid_input = GridInput::example();
+        grid_input.phrases = (0..20)
+            .map(|i| GridInputPhrase {
+                texts: vec![Text::from_str(&"A".repeat(i + 1)).unwrap()],
+                language: None,
+                enabled: true,
+            })
+            .collect();
+        let sample = |grid_input: &GridInput, max_phrases| {
+            let mut grid_input = grid_input.clone();
+            let random = &mut SmallRng::seed_from_u64(17);
+            let dropped = grid_input.sample_phrases(max_phrases, random);
+            let lens = |phrases: &[GridInputPhrase]| {
+                phrases
+                    .iter()
+                    .map(|phrase| phrase.texts[0].letters().len())
+                    .collect_vec()
+            };
+            (lens(&grid_input.phrases), lens(&dropped))
+        };
+
+        let (kept, dropped) = sample(&grid_input, 7);
+        assert_eq!(kept.len(), 7);
+        assert_eq!(dropped.len(), 13);
+        assert!(kept.iter().tuple_windows().all(|(a, b)| a < b));
+        assert_eq!(sample(&grid_input, 7), (kept, dropped));
+
+        let (kept, dropped) = sample(&grid_input, 20);
+        assert_eq!(kept, (1..=20).collect_vec());
+        assert!(dropped.is_empty());
+    }
+
+    #[test]
+    fn content_seed() {
+        let seed = GridInput::example().content_seed();
+        assert_eq!(GridInput::example().content_seed(), seed);
+
+        let mut other = GridInput::example();
+        other.phrases[1].texts = texts("IT IS THREE");
+        assert_ne!(other.content_seed(), seed);
+    }
+
+    #[test]
+    fn migrate() {
+        let json = r#"{"minimal_grid":[["A"]],"grid":[["A"]],"phrases":[]}"#;
+        let grid: GridOutput = serde_json::from_str(json).unwrap();
+        assert_eq!(grid.version, 0);
+        assert_eq!(grid.migrate().unwrap().version, GRID_OUTPUT_VERSION);
+
+        let json = r#"{"version":99,"minimal_grid":[["A"]],"grid":[["A"]],"phrases":[]}"#;
+        let grid: GridOutput = serde_json::from_str(json).unwrap();
+        assert!(grid.migrate().is_err());
+
+        let json = r#"{"minimal_grid":[["A"]],"grid":[["A","B"],["C"]],"phrases":[]}"#;
+        let grid: GridOutput = serde_json::from_str(json).unwrap();
+        assert!(grid.migrate().is_err());
+
+        let json = r#"{"minimal_grid":[["A","B"]],"grid":[["A"],["C"]],"phrases":[]}"#;
+        let grid: GridOutput = serde_json::from_str(json).unwrap();
+        assert!(grid.migrate().is_err());
+    }
+
+    #[test]
+    fn word_text() {
+        let json = r#"{"version":1,"minimal_grid":[["H","I"]],"grid":[["X","X","X"],["X","H","I"]],
+            "phrases":[{"words":[{"letters":[[1,1],[2,1]]}]}]}"#;
+        let grid: GridOutput = serde_json::from_str(json).unwrap();
+        let grid = grid.migrate().unwrap();
+
+        let word = &grid.phrases[0].words[0];
+        assert_eq!(word.text, "HI");
+        assert_eq!(word.read(&grid.grid).unwrap(), word.text);
+
+        let outside = GridOutputWord {
+            letters: vec![(3, 1)],
+            text: String::new(),
+        };
+        assert!(outside.read(&grid.grid).is_err());
+    }
+
+    #[test]
+    fn coord_order() {
+        // "HI" written horizontally on the second row
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: vec![vec![Some(Letter::H), Some(Letter::I)]],
+            grid: vec![
+                vec![Letter::X, Letter::X, Letter::X],
+                vec![Letter::X, Letter::H, Letter::I],
+            ],
+            fixed_cells: vec![],
+            phrases: vec![GridOutputPhrase {
+                words: vec![GridOutputWord {
+                    letters: vec![(1, 1), (2, 1)],
+                    text: "HI".to_owned(),
+                }],
+            }],
+        };
+        let letters = |grid: GridOutput| grid.phrases[0].words[0].letters.clone();
+
+        let xy = grid.clone().with_coord_order(CoordOrder::Xy);
+        assert_eq!(letters(xy), [(1, 1), (2, 1)]);
+        let row_col = grid.with_coord_order(CoordOrder::RowCol);
+        assert_eq!(letters(row_col), [(1, 1), (1, 2)]);
+    }
+
+    #[test]
+    fn split_and_recombine() {
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: vec![vec![Some(Letter::H), Some(Letter::I)]],
+            grid: vec![
+                vec![Letter::X, Letter::X, Letter::X],
+                vec![Letter::X, Letter::H, Letter::I],
+            ],
+            fixed_cells: vec![],
+            phrases: vec![GridOutputPhrase {
+                words: vec![GridOutputWord {
+                    letters: vec![(1, 1), (2, 1)],
+                    text: "HI".to_owned(),
+                }],
+            }],
+        };
+
+        let (minimal, filled) = grid.clone().split((1, 1));
+        assert_eq!(minimal.phrases[0].words[0].letters, vec![(0, 0), (1, 0)]);
+
+        let recombined = GridOutput::from_parts(minimal, filled);
+        assert_eq!(
+            serde_json::to_string(&recombined).unwrap(),
+            serde_json::to_string(&grid).unwrap()
+        );
+    }
+
+    #[test]
+    fn stats() {
+        let word = |letters: &[(i16, i16)], text: &str| GridOutputWord {
+            letters: letters.to_vec(),
+            text: text.to_owned(),
+        };
+        // HI, IT and the diagonal IS cross at the "I", while ON stands apart
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: vec![],
+            grid: vec![
+                vec![Letter::H, Letter::I, Letter::T, Letter::X],
+                vec![Letter::X, Letter::X, Letter::S, Letter::X],
+                vec![Letter::X, Letter::X, Letter::X, Letter::X],
+                vec![Letter::O, Letter::N, Letter::X, Letter::X],
+            ],
+            fixed_cells: vec![],
+            phrases: vec![
+                GridOutputPhrase {
+                    words: vec![word(&[(0, 0), (1, 0)], "HI"), word(&[(0, 3), (1, 3)], "ON")],
+                },
+                GridOutputPhrase {
+                    words: vec![word(&[(1, 0), (2, 0)], "IT"), word(&[(1, 0), (2, 1)], "IS")],
+                },
+                GridOutputPhrase {
+                    words: vec![word(&[(0, 0), (1, 0)], "HI")],
+                },
+            ],
+        };
+
+        let stats = grid.stats();
+        assert_eq!((stats.width, stats.height), (4, 4));
+        assert_eq!(stats.num_letters, 6);
+        assert_eq!(stats.crossing_count, 1);
+        assert_eq!(stats.diagonal_fraction, 0.25);
+        assert_eq!(stats.decoy_count, 10);
+        assert_eq!(stats.packing_density, 6. / 16.);
+        assert_eq!(stats.connected_components, 2);
+    }
+
+    #[test]
+    fn canonical_key() {
+        let grid = GridOutput {
+            version: GRID_OUTPUT_VERSION,
+            minimal_grid: vec![vec![Some(Letter::H), Some(Letter::I)]],
+            grid: vec![
+                vec![Letter::X, Letter::X, Letter::X],
+                vec![Letter::X, Letter::H, Letter::I],
+            ],
+            fixed_cells: vec![],
+            phrases: vec![GridOutputPhrase {
+                words: vec![GridOutputWord {
+                    letters: vec![(1, 1), (2, 1)],
+                    text: "HI".to_owned(),
+                }],
+            }],
+        };
+
+        // The same layout with another fill around it
+        let (minimal, _) = grid.clone().split((1, 1));
+        let refilled = GridOutput::from_parts(
+            minimal,
+            FilledGridOutput {
+                grid: vec![
+                    vec![Letter::H, Letter::I, Letter::Y],
+                    vec![Letter::Z, Letter::Z, Letter::Z],
+                ],
+                fixed_cells: vec![],
+                minimal_offset: (0, 0),
+            },
+        );
+        assert_eq!(refilled.canonical_key(), grid.canonical_key());
+
+        let mut other = grid.clone();
+        other.minimal_grid = vec![vec![Some(Letter::H), Some(Letter::O)]];
+        assert_ne!(other.canonical_key(), grid.canonical_key());
+    }
+}
@@ -1,8 +1,11 @@
 use crate::models::time::Time;
 
-use crate::generate_phrases::{english, french, german, portuguese};
+use crate::generate_phrases::{
+    dutch, english, french, german, greek, italian, portuguese, spanish, SpellOptions,
+};
 use crate::models::text::Text;
 use anyhow::{anyhow, Error};
+use itertools::Itertools;
 use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 
@@ -11,36 +14,142 @@ use std::str::FromStr;
 pub enum Language {
     English,
     French,
+    /// French as spoken in Switzerland, which only differs in the numbers from 70 to 99, so the
+    /// phrases of a clock are the same as in French
+    FrenchSwiss,
     Portuguese,
     German,
+    /// German, counting the minutes past or to the hour instead of relative to the half hour
+    GermanMinutes,
+    /// Transliterated to Latin letters
+    Greek,
+    Spanish,
+    Italian,
+    Dutch,
+    /// A synthetic language, with deterministic and regular phrases
+    #[cfg(test)]
+    Test,
 }
 
 impl FromStr for Language {
     type Err = Error;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "English" => Ok(Language::English),
-            "French" => Ok(Language::French),
-            "Portuguese" => Ok(Language::Portuguese),
-            "German" => Ok(Language::German),
-            _ => Err(anyhow!("Language was not recognized: {}", s)),
-        }
+        let s = s.trim();
+        Language::all()
+            .into_iter()
+            .find(|language| language.tag().eq_ignore_ascii_case(s))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Language was not recognized: {}. Available languages are: {}",
+                    s,
+                    Language::all().into_iter().map(Language::tag).format(", ")
+                )
+            })
     }
 }
 
 impl Language {
-    pub fn spell(self, time: Time) -> Vec<Text> {
+    /// Return all the languages that can be requested
+    pub fn all() -> Vec<Language> {
+        vec![
+            Language::English,
+            Language::French,
+            Language::FrenchSwiss,
+            Language::Portuguese,
+            Language::German,
+            Language::GermanMinutes,
+            Language::Greek,
+            Language::Spanish,
+            Language::Italian,
+            Language::Dutch,
+        ]
+    }
+
+    /// Return the name used to request this language
+    pub fn tag(self) -> &'static str {
+        match self {
+            Language::English => "English",
+            Language::French => "French",
+            Language::FrenchSwiss => "FrenchSwiss",
+            Language::Portuguese => "Portuguese",
+            Language::German => "German",
+            Language::GermanMinutes => "GermanMinutes",
+            Language::Greek => "Greek",
+            Language::Spanish => "Spanish",
+            Language::Italian => "Italian",
+            Language::Dutch => "Dutch",
+            #[cfg(test)]
+            Language::Test => "Test",
+        }
+    }
+
+    /// Return an example of a language spec, showing the options supported by this language
+    pub fn example_spec(self) -> String {
+        match self {
+            Language::Portuguese => format!("{}+period:5", self.tag()),
+            _ => format!("{}:5", self.tag()),
+        }
+    }
+
+    pub fn spell(self, time: Time, options: SpellOptions) -> Vec<Text> {
+        let rollover = options.rollover;
         let phrase = match self {
-            Language::English => english::spell(time),
-            Language::French => french::spell(time),
-            Language::Portuguese => portuguese::spell(time),
-            Language::German => german::spell(time),
+            Language::English => english::spell(time, rollover),
+            Language::French => french::spell(time, rollover, french::Dialect::France),
+            Language::FrenchSwiss => french::spell(time, rollover, french::Dialect::Swiss),
+            Language::Portuguese => portuguese::spell(time, rollover, options.period_suffix),
+            Language::German => german::spell(time, rollover, german::Style::HalfHour),
+            Language::GermanMinutes => german::spell(time, rollover, german::Style::Minutes),
+            Language::Greek => greek::spell(time, rollover),
+            Language::Spanish => spanish::spell(time, rollover),
+            Language::Italian => italian::spell(time, rollover),
+            Language::Dutch => dutch::spell(time, rollover),
+            #[cfg(test)]
+            Language::Test => crate::generate_phrases::test_language::spell(time, rollover),
         };
 
-        phrase
-            .split(' ')
-            .map(|word| word.parse().expect("Valid Text"))
-            .collect()
+        split_words(&phrase)
+    }
+}
+
+/// Split a spelled phrase into its words, ignoring any extra whitespace between them
+fn split_words(phrase: &str) -> Vec<Text> {
+    phrase
+        .split_whitespace()
+        .map(|word| word.parse().expect("Valid Text"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags() {
+        for language in Language::all() {
+            let parsed: Language = language.tag().parse().unwrap();
+            assert_eq!(parsed.tag(), language.tag());
+        }
+    }
+
+    #[test]
+    fn parse_ignoring_case() {
+        for s in ["english", "ENGLISH", "English", "eNgLiSh", " English\t"] {
+            let parsed: Language = s.parse().unwrap();
+            assert_eq!(parsed.tag(), "English");
+        }
+        let parsed: Language = "germanminutes".parse().unwrap();
+        assert_eq!(parsed.tag(), "GermanMinutes");
+
+        let error = "Klingon".parse::<Language>().unwrap_err().to_string();
+        assert!(error.starts_with("Language was not recognized: Klingon."));
+        assert!(error.contains("English, French"));
+    }
+
+    #[test]
+    fn split_words() {
+        let expected: Vec<Text> = vec!["ONE".parse().unwrap(), "TWO".parse().unwrap()];
+        assert_eq!(super::split_words(" ONE  TWO "), expected);
     }
 }
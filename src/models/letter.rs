@@ -40,6 +40,43 @@ pub enum Letter {
 }
 
 impl Letter {
+    /// Iterate over all letters, in alphabetical order
+    pub fn all() -> impl Iterator<Item = Letter> {
+        ('A'..='Z').map(|c| c.try_into().expect("Must be a valid letter"))
+    }
+
+    /// The relative frequency of this letter in English texts, in percent
+    pub fn frequency(self) -> f64 {
+        match self {
+            Letter::A => 8.2,
+            Letter::B => 1.5,
+            Letter::C => 2.8,
+            Letter::D => 4.3,
+            Letter::E => 12.7,
+            Letter::F => 2.2,
+            Letter::G => 2.0,
+            Letter::H => 6.1,
+            Letter::I => 7.0,
+            Letter::J => 0.15,
+            Letter::K => 0.77,
+            Letter::L => 4.0,
+            Letter::M => 2.4,
+            Letter::N => 6.7,
+            Letter::O => 7.5,
+            Letter::P => 1.9,
+            Letter::Q => 0.095,
+            Letter::R => 6.0,
+            Letter::S => 6.3,
+            Letter::T => 9.1,
+            Letter::U => 2.8,
+            Letter::V => 0.98,
+            Letter::W => 2.4,
+            Letter::X => 0.15,
+            Letter::Y => 2.0,
+            Letter::Z => 0.074,
+        }
+    }
+
     pub fn as_char(self) -> char {
         match self {
             Letter::A => 'A',
@@ -1,17 +1,63 @@
-use anyhow::{ensure, Result};
+use anyhow::{ensure, Context, Result};
 use itertools::Itertools;
 use petgraph::algo::DfsSpace;
 use petgraph::dot::{Config, Dot};
 use petgraph::prelude::{NodeIndex, StableDiGraph};
-use petgraph::visit::IntoNodeReferences;
+use petgraph::visit::{EdgeRef, IntoEdgeReferences, IntoNodeReferences};
 use petgraph::{algo, Direction};
 use std::collections::BTreeMap;
+use std::ffi::OsString;
 use std::fmt::Display;
 use std::io::Write;
 use std::ops::Index;
 use std::path::Path;
 use std::process::{Command, Stdio};
-use std::{fs, mem};
+use std::time::Duration;
+use std::{fs, mem, thread};
+
+/// How many times `dot` is run before giving up
+const DOT_ATTEMPTS: u32 = 3;
+
+/// Run the program with the given standard input until it succeeds, at most `attempts` times. The
+/// error includes the standard error of the last attempt.
+fn run_with_retries(program: &str, args: &[OsString], input: &[u8], attempts: u32) -> Result<()> {
+    let mut attempt = 1;
+    loop {
+        let result = (|| -> Result<()> {
+            let mut child = Command::new(program)
+                .args(args)
+                .stdin(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()?;
+            child.stdin.take().unwrap().write_all(input)?;
+            let output = child.wait_with_output()?;
+            ensure!(
+                output.status.success(),
+                "{} exited with {}: {}",
+                program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+            Ok(())
+        })();
+
+        match result {
+            Err(error) if attempt < attempts => {
+                log::warn!("Attempt {} to run {} failed: {:#}", attempt, program, error);
+                thread::sleep(Duration::from_millis(200 * attempt as u64));
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
 
 /// Represents a direct acyclic graph, whose nodes can be grouped together.
 ///
@@ -54,8 +100,10 @@ impl<NodeId: Copy + Ord, Group> MergeDag<NodeId, Group> {
         }
     }
 
-    /// Merge two groups together (as identified by one of their node ids). The `group_b` is
-    /// removed from the graph and the `group_a` is modified in place by the callback `merge`.
+    /// Merge two groups together (as identified by one of their node ids). The callback `merge`
+    /// receives both groups and returns the merged one, which replaces `group_a`, while `group_b`
+    /// is removed from the graph. When the callback returns `None`, the merge is rejected and the
+    /// graph is left unchanged. Return whether the groups were merged.
     ///
     /// # Panic
     /// This will panic if both nodes are part of the same group
@@ -63,12 +111,20 @@ impl<NodeId: Copy + Ord, Group> MergeDag<NodeId, Group> {
         &mut self,
         group_a: GroupId,
         group_b: GroupId,
-        merge: impl FnOnce(&mut Group, Group),
-    ) {
+        merge: impl FnOnce(&Group, &Group) -> Option<Group>,
+    ) -> bool {
         assert_ne!(group_a, group_b);
         let group_a = group_a.0;
         let group_b = group_b.0;
 
+        // Ask for the merged group before touching the graph, so that a rejection has nothing to
+        // roll back
+        let graph = &mut self.merged_graph;
+        let merged = match merge(&graph[group_a], &graph[group_b]) {
+            None => return false,
+            Some(merged) => merged,
+        };
+
         // Update mapping of groups, from `b` to `a`
         for target_group in self.group_by_node.values_mut() {
             if *target_group == group_b {
@@ -77,7 +133,6 @@ impl<NodeId: Copy + Ord, Group> MergeDag<NodeId, Group> {
         }
 
         // Copy all edges from `b` to `a`: incoming and outgoing
-        let graph = &mut self.merged_graph;
         let mut neighbors = graph
             .neighbors_directed(group_b, Direction::Incoming)
             .detach();
@@ -96,8 +151,9 @@ impl<NodeId: Copy + Ord, Group> MergeDag<NodeId, Group> {
         }
 
         // Merge groups
-        let group = graph.remove_node(group_b).unwrap();
-        merge(&mut graph[group_a], group);
+        graph.remove_node(group_b);
+        graph[group_a] = merged;
+        true
     }
 
     pub fn nodes_len(&self) -> usize {
@@ -115,6 +171,13 @@ impl<NodeId: Copy + Ord, Group> MergeDag<NodeId, Group> {
             .map(|(id, group)| (GroupId(id), group))
     }
 
+    /// Return each ordering edge, from the group that comes before to the one that comes after
+    pub fn edges(&self) -> impl Iterator<Item = (GroupId, GroupId)> + '_ {
+        self.merged_graph
+            .edge_references()
+            .map(|edge| (GroupId(edge.source()), GroupId(edge.target())))
+    }
+
     pub fn groups_len(&self) -> usize {
         self.merged_graph.node_count()
     }
@@ -130,6 +193,36 @@ impl<NodeId: Copy + Ord, Group> MergeDag<NodeId, Group> {
         Dot::with_config(&debug_graph, &[Config::EdgeNoLabel]).to_string()
     }
 
+    /// Represent the graph in the GraphML format, with each group labelled by its text
+    pub fn graphml(&self) -> String
+    where
+        Group: Display,
+    {
+        let mut graphml = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n\
+            <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n\
+            <graph edgedefault=\"directed\">\n",
+        );
+        for (id, group) in self.merged_graph.node_references() {
+            graphml += &format!(
+                "<node id=\"n{}\"><data key=\"label\">{}</data></node>\n",
+                id.index(),
+                escape_xml(&group.to_string())
+            );
+        }
+        for edge in self.merged_graph.edge_references() {
+            graphml += &format!(
+                "<edge source=\"n{}\" target=\"n{}\"/>\n",
+                edge.source().index(),
+                edge.target().index()
+            );
+        }
+        graphml += "</graph>\n</graphml>\n";
+
+        graphml
+    }
+
     /// Save the graph as a SVG file.
     ///
     /// This requires that a binary called `dot` be available. Tested with version 2.43.0.
@@ -142,23 +235,19 @@ impl<NodeId: Copy + Ord, Group> MergeDag<NodeId, Group> {
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
         }
-        let mut command = Command::new("dot");
-        command
-            .args(&["-T", "svg", "-Gsplines=ortho", "-o"])
-            .arg(path);
+        let mut args: Vec<OsString> = vec![
+            "-T".into(),
+            "svg".into(),
+            "-Gsplines=ortho".into(),
+            "-o".into(),
+            path.into(),
+        ];
         if log::log_enabled!(log::Level::Debug) {
-            command.arg("-v");
+            args.push("-v".into());
         }
-        let mut dot = command.stdin(Stdio::piped()).spawn()?;
 
-        dot.stdin
-            .as_ref()
-            .unwrap()
-            .write_all(self.dot().as_bytes())?;
-
-        ensure!(dot.wait()?.success(), "Failed to generate SVG");
-
-        Ok(())
+        run_with_retries("dot", &args, self.dot().as_bytes(), DOT_ATTEMPTS)
+            .context("Failed to generate SVG")
     }
 
     /// Return if there is any path connecting the two groups
@@ -198,6 +287,35 @@ impl<NodeId: Copy + Ord, Group> MergeDag<NodeId, Group> {
         result
     }
 
+    /// Return the longest path in the whole graph, also known as the critical path
+    pub fn longest_path(&self) -> Vec<GroupId> {
+        let graph = &self.merged_graph;
+        let sorted = algo::toposort(graph, None).expect("The graph must be acyclic");
+
+        // For each node, the length of the longest path ending at it and the previous node in it
+        let mut best: BTreeMap<NodeIndex<u16>, (usize, Option<NodeIndex<u16>>)> = BTreeMap::new();
+        for &node in &sorted {
+            let entry = graph
+                .neighbors_directed(node, Direction::Incoming)
+                .map(|parent| (best[&parent].0 + 1, Some(parent)))
+                .max()
+                .unwrap_or((1, None));
+            best.insert(node, entry);
+        }
+
+        let mut path = vec![];
+        let mut next = best
+            .iter()
+            .max_by_key(|(_, &(length, _))| length)
+            .map(|(&node, _)| node);
+        while let Some(node) = next {
+            path.push(GroupId(node));
+            next = best[&node].1;
+        }
+        path.reverse();
+        path
+    }
+
     /// Return the size of the longest chain of tokens in each direction (incoming, outgoing)
     pub fn longest_chain_size(&self, group: GroupId) -> LongestChainSize {
         LongestChainSize {
@@ -252,3 +370,90 @@ impl<NodeId, Group> Index<GroupId> for MergeDag<NodeId, Group> {
         &self.merged_graph[index.0]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_with_retries() {
+        let script = |script: &str| vec!["-c".into(), script.into()];
+
+        assert!(super::run_with_retries("sh", &script("cat > /dev/null"), b"input", 3).is_ok());
+
+        let error =
+            super::run_with_retries("sh", &script("echo oops >&2; exit 1"), b"", 2).unwrap_err();
+        assert!(error.to_string().contains("oops"));
+
+        assert!(super::run_with_retries("not-a-command", &[], b"", 1).is_err());
+    }
+
+    #[test]
+    fn merge_groups() {
+        let groups = vec![
+            (0, "A".to_owned()),
+            (1, "B".to_owned()),
+            (2, "C".to_owned()),
+        ];
+        let mut graph = MergeDag::new(groups, &[(0, 1), (2, 1)]);
+        let group = |graph: &MergeDag<_, _>, node| graph.group(node).0;
+        let before = graph.graphml();
+
+        let (a, c) = (group(&graph, 0), group(&graph, 2));
+        assert!(!graph.merge_groups(a, c, |_, _| None));
+        assert_eq!(graph.graphml(), before);
+        assert_ne!(group(&graph, 0), group(&graph, 2));
+
+        assert!(graph.merge_groups(a, c, |a, c| Some(format!("{}{}", a, c))));
+        assert_eq!(graph.groups_len(), 2);
+        assert_eq!(group(&graph, 0), group(&graph, 2));
+        assert_eq!(graph.group(2).1, "AC");
+    }
+
+    #[test]
+    fn graphml() {
+        let groups = vec![(0, "A&B"), (1, "<C>"), (2, "D")];
+        let graph = MergeDag::new(groups, &[(0, 1), (1, 2), (0, 2)]);
+
+        let graphml = graph.graphml();
+        assert!(graphml.starts_with("<?xml"));
+        assert_eq!(graphml.matches("<node ").count(), 3);
+        assert_eq!(graphml.matches("<edge ").count(), 3);
+        assert!(graphml.contains(">A&amp;B<") && graphml.contains(">&lt;C&gt;<"));
+        assert!(graphml.contains("<edge source=\"n0\" target=\"n1\"/>"));
+
+        // Every opened element is closed, in order
+        let mut open = vec![];
+        for tag in graphml.split('<').skip(1) {
+            let tag = &tag[..tag.find('>').unwrap()];
+            let name = tag
+                .trim_start_matches('/')
+                .split_whitespace()
+                .next()
+                .unwrap();
+            if tag.starts_with('?') || tag.ends_with('/') {
+                continue;
+            } else if tag.starts_with('/') {
+                assert_eq!(open.pop(), Some(name));
+            } else {
+                open.push(name);
+            }
+        }
+        assert!(open.is_empty());
+    }
+
+    #[test]
+    fn longest_path() {
+        // 0 -> 1 -> 2 -> 3 is longer than 4 -> 2 and 0 -> 5
+        let groups = (0..6).map(|node| (node, node)).collect();
+        let edges = [(0, 1), (1, 2), (2, 3), (4, 2), (0, 5)];
+        let graph = MergeDag::new(groups, &edges);
+
+        let path = graph
+            .longest_path()
+            .into_iter()
+            .map(|group| graph[group])
+            .collect_vec();
+        assert_eq!(path, vec![0, 1, 2, 3]);
+    }
+}
@@ -1,6 +1,8 @@
 pub mod aspect_ratio;
+pub mod explanation;
 pub mod grid;
 pub mod grid_bag;
+pub mod io;
 pub mod language;
 pub mod letter;
 pub mod merge_dag;
@@ -13,4 +15,3 @@ pub mod time;
 pub mod token;
 pub mod token_relations;
 pub mod word;
-pub mod io;
@@ -13,6 +13,25 @@ pub struct TimePhrase {
     pub texts: Vec<Text>,
 }
 
+impl TimePhrase {
+    pub fn hours(&self) -> u8 {
+        self.time.hours()
+    }
+
+    pub fn minutes(&self) -> u8 {
+        self.time.minutes()
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Return the words of the phrase, in reading order
+    pub fn words(&self) -> &[Text] {
+        &self.texts
+    }
+}
+
 /// Represents a phrase
 #[derive(Debug, Clone)]
 pub struct Phrase {
@@ -22,3 +41,27 @@ pub struct Phrase {
 
 #[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
 pub struct PhraseId(pub u16);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    #[test]
+    fn time_phrase() {
+        let phrase = TimePhrase {
+            language: Language::English,
+            time: Time::new(13, 45),
+            texts: vec![
+                "QUARTER".parse().unwrap(),
+                "TO".parse().unwrap(),
+                "TWO".parse().unwrap(),
+            ],
+        };
+
+        assert_eq!(phrase.hours(), 13);
+        assert_eq!(phrase.minutes(), 45);
+        assert_eq!(phrase.language().tag(), "English");
+        assert_eq!(phrase.words().iter().join(" "), "QUARTER TO TWO");
+    }
+}
@@ -1,6 +1,8 @@
 use crate::models::phrase::{Phrase, PhraseId};
 use crate::models::text::Text;
 use crate::models::word::{Word, WordId};
+use anyhow::{ensure, Result};
+use std::collections::BTreeMap;
 use std::ops::Index;
 
 /// Represents all phrases that we want to write in the final grid
@@ -8,9 +10,32 @@ use std::ops::Index;
 pub struct PhraseBook {
     phrases: Vec<Phrase>,
     words: Vec<Word>,
+    /// The canonical text of each synonym, see [`PhraseBook::set_synonyms()`]
+    canonical_texts: BTreeMap<Text, Text>,
 }
 
 impl PhraseBook {
+    /// Write the words of each group like its first one, so that they merge into the same token.
+    /// For example, with the group "COLOR COLOUR", "COLOUR" is inserted as "COLOR". This only
+    /// applies to the phrases inserted afterwards.
+    pub fn set_synonyms(&mut self, groups: &[Vec<Text>]) -> Result<()> {
+        let mut canonical_texts = BTreeMap::new();
+        for group in groups {
+            if let Some((canonical, synonyms)) = group.split_first() {
+                for synonym in synonyms {
+                    let previous = canonical_texts.insert(synonym.clone(), canonical.clone());
+                    ensure!(
+                        previous.is_none(),
+                        "{} is in more than one synonym group",
+                        synonym
+                    );
+                }
+            }
+        }
+        self.canonical_texts = canonical_texts;
+        Ok(())
+    }
+
     pub fn insert_phrase(&mut self, phrase: Vec<Text>) -> PhraseId {
         let mut words = vec![];
         for word in phrase {
@@ -22,11 +47,27 @@ impl PhraseBook {
         id
     }
 
+    /// Create a book with the given phrases, whose words are separated by spaces
+    #[cfg(test)]
+    pub fn from_phrases(phrases: &[&str]) -> Self {
+        let mut phrase_book = PhraseBook::default();
+        for phrase in phrases {
+            phrase_book.insert_phrase(
+                phrase
+                    .split(' ')
+                    .map(|word| word.parse().unwrap())
+                    .collect(),
+            );
+        }
+        phrase_book
+    }
+
     pub fn phrases(&self) -> &[Phrase] {
         &self.phrases
     }
 
     fn insert_word(&mut self, text: Text) -> WordId {
+        let text = self.canonical_texts.get(&text).cloned().unwrap_or(text);
         let id = WordId(self.words.len() as u16);
         self.words.push(Word { id, text });
         id
@@ -40,3 +81,34 @@ impl Index<WordId> for PhraseBook {
         &self.words[index.0 as usize]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tokenize::{tokenize, MergeObjective};
+
+    fn texts(words: &str) -> Vec<Text> {
+        words.split(' ').map(|word| word.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn synonyms() {
+        let mut phrase_book = PhraseBook::default();
+        phrase_book
+            .set_synonyms(&[texts("COLOR COLOUR"), texts("GRAY GREY")])
+            .unwrap();
+        phrase_book.insert_phrase(texts("RED COLOR"));
+        phrase_book.insert_phrase(texts("BLUE COLOUR"));
+
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let phrases = phrase_book.phrases();
+        let color = token_graph.group(phrases[0].words[1]);
+        let colour = token_graph.group(phrases[1].words[1]);
+        assert_eq!(color.0, colour.0);
+        assert_eq!(colour.1.text().to_string(), "COLOR");
+
+        assert!(phrase_book
+            .set_synonyms(&[texts("COLOR COLOUR"), texts("HUE COLOUR")])
+            .is_err());
+    }
+}
@@ -1,75 +1,320 @@
+use crate::models::letter::Letter;
+use crate::models::positioned_token::XY;
 use crate::models::positioned_token::{Direction, OrientedToken, PositionedToken};
+use crate::models::token::Token;
 use crate::models::token_relations::{TokenRelation, TokenRelations};
-use crate::XY;
+use anyhow::{anyhow, Error};
 use std::collections::BTreeSet;
+use std::str::FromStr;
 
 /// Represent a space where a token can start, so that the "before" and "after" restrictions are
 /// respected
 #[derive(Debug, Clone)]
 pub struct PositionRestriction {
+    reading_axis: ReadingAxis,
+    /// The positions below are expressed in the reading space, see [`ReadingAxis::to_reading`]
     min_start: Option<XY>,
+    /// Only set when a token that must come "after" was already positioned, as when the grid is
+    /// seeded with some tokens
+    max_start: Option<XY>,
     forbidden_starts: BTreeSet<XY>,
 }
 
+/// Determine what "before" means for two tokens of the same phrase
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReadingAxis {
+    /// The grid is read row by row: "before" means "above" or "left-of" in the same row
+    RowMajor,
+    /// The grid is read column by column: "before" means "left-of" or "above" in the same column
+    ColumnMajor,
+}
+
 impl PositionRestriction {
-    /// # Panics
-    /// It will panic if any of the positioned token is required to be "after" the given token
     pub fn new(
         relations: &TokenRelations,
         positioned_tokens: &[PositionedToken],
         token: OrientedToken,
+        reading_axis: ReadingAxis,
     ) -> Self {
         let mut forbidden_starts = BTreeSet::new();
+        let mut min_start = None;
+        let mut max_start = None;
 
-        let min_start = positioned_tokens
-            .iter()
-            .filter_map(
-                |positioned| match relations.get(positioned.token_id(), token.token_id()) {
-                    TokenRelation::IsAfter => unreachable!(),
-                    TokenRelation::None => None,
-                    TokenRelation::IsBefore => {
-                        let end = positioned.end();
-
-                        // Forbid the grid space that represents the "continuation" of this token.
-                        // The token should start at least on the "next" grid space.
-                        match positioned.direction() {
-                            Direction::Point => {
-                                forbidden_starts.insert(end + XY::new(0, 1));
-                                forbidden_starts.insert(end + XY::new(1, 1));
-                                Some(end + XY::new(2, 0))
-                            }
-                            Direction::Horizontal => Some(end + XY::new(2, 0)),
-                            Direction::Vertical => {
-                                forbidden_starts.insert(end + XY::new(0, 1));
-                                Some(end + XY::new(1, 0))
-                            }
-                            Direction::Diagonal => {
-                                forbidden_starts.insert(end + XY::new(1, 1));
-                                Some(end + XY::new(1, 0))
-                            }
-                        }
-                    }
-                },
-            )
-            .max();
+        for &positioned in positioned_tokens {
+            match relations.get(positioned.token_id(), token.token_id()) {
+                TokenRelation::None => {}
+                TokenRelation::IsBefore => {
+                    // The token should start at least on the "next" grid space
+                    let end = reading_axis.to_reading(positioned.end());
+                    let (min_offset, forbidden_offsets) =
+                        next_start_offsets(reading_axis.direction(positioned.direction()));
+                    forbidden_starts
+                        .extend(forbidden_offsets.into_iter().map(|offset| end + offset));
+                    min_start = min_start.max(Some(end + min_offset));
+                }
+                TokenRelation::IsAfter => {
+                    // The token should end so that the positioned one starts on the "next" grid
+                    // space
+                    let direction = reading_axis.direction(token.direction());
+                    let span = direction.as_xy() * (token.size() - 1);
+                    let base = reading_axis.to_reading(positioned.start()) - span;
+                    let (min_offset, forbidden_offsets) = next_start_offsets(direction);
+                    forbidden_starts
+                        .extend(forbidden_offsets.into_iter().map(|offset| base - offset));
+                    let start = base - min_offset;
+                    max_start = Some(max_start.map_or(start, |max_start: XY| max_start.min(start)));
+                }
+            }
+        }
 
         PositionRestriction {
+            reading_axis,
             min_start,
+            max_start,
             forbidden_starts,
         }
     }
 
+    /// Also forbid the token from starting right next to the end of the token before it, in any
+    /// of the 8 directions, when the letter at that boundary is the same, like "ONE" and "EIGHT".
+    /// The same applies to ending next to the start of the token after it. Otherwise, the
+    /// repeated letter could be read as a single one shared by both tokens.
+    ///
+    /// `letter_at` returns the letters already in the grid.
+    pub fn separate_shared_boundaries(
+        &mut self,
+        relations: &TokenRelations,
+        positioned_tokens: &[PositionedToken],
+        token: &Token,
+        oriented: OrientedToken,
+        letter_at: impl Fn(XY) -> Option<Letter>,
+    ) {
+        let reading_axis = self.reading_axis;
+        let neighborhood = |center: XY| {
+            (-1..=1).flat_map(move |dy| (-1..=1).map(move |dx| center + XY::new(dx, dy)))
+        };
+
+        for &positioned in positioned_tokens {
+            match relations.get(positioned.token_id(), oriented.token_id()) {
+                TokenRelation::None => {}
+                TokenRelation::IsBefore => {
+                    if letter_at(positioned.end()) == Some(token.first_letter()) {
+                        let end = reading_axis.to_reading(positioned.end());
+                        self.forbidden_starts.extend(neighborhood(end));
+                    }
+                }
+                TokenRelation::IsAfter => {
+                    if letter_at(positioned.start()) == Some(token.last_letter()) {
+                        let direction = reading_axis.direction(oriented.direction());
+                        let span = direction.as_xy() * (oriented.size() - 1);
+                        let base = reading_axis.to_reading(positioned.start()) - span;
+                        self.forbidden_starts.extend(neighborhood(base));
+                    }
+                }
+            }
+        }
+    }
+
     pub fn min_start(&self) -> Option<XY> {
         self.min_start
+            .map(|start| self.reading_axis.to_reading(start))
     }
 
-    pub fn is_valid_start(&self, start: XY) -> bool {
+    pub fn max_start(&self) -> Option<XY> {
+        self.max_start
+            .map(|start| self.reading_axis.to_reading(start))
+    }
+
+    /// Return whether the start respects both the minimum and maximum starts
+    pub fn is_in_range(&self, start: XY) -> bool {
+        let start = self.reading_axis.to_reading(start);
+
         if let Some(min_start) = self.min_start {
             if start < min_start {
                 return false;
             }
         }
 
-        !self.forbidden_starts.contains(&start)
+        if let Some(max_start) = self.max_start {
+            if start > max_start {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn is_valid_start(&self, start: XY) -> bool {
+        self.is_in_range(start)
+            && !self
+                .forbidden_starts
+                .contains(&self.reading_axis.to_reading(start))
+    }
+}
+
+impl ReadingAxis {
+    /// Convert a grid position into the "reading space", where positions are compared row by row.
+    /// For [`ReadingAxis::ColumnMajor`], this transposes the grid. Since transposing is its own
+    /// inverse, this also converts back into a grid position.
+    fn to_reading(self, pos: XY) -> XY {
+        match self {
+            ReadingAxis::RowMajor => pos,
+            ReadingAxis::ColumnMajor => XY::new(pos.y, pos.x),
+        }
+    }
+
+    /// Convert a direction into the "reading space"
+    fn direction(self, direction: Direction) -> Direction {
+        match (self, direction) {
+            (ReadingAxis::ColumnMajor, Direction::Horizontal) => Direction::Vertical,
+            (ReadingAxis::ColumnMajor, Direction::Vertical) => Direction::Horizontal,
+            (_, direction) => direction,
+        }
+    }
+}
+
+impl FromStr for ReadingAxis {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "row-major" => Ok(ReadingAxis::RowMajor),
+            "column-major" => Ok(ReadingAxis::ColumnMajor),
+            _ => Err(anyhow!("Reading axis was not recognized: {}", s)),
+        }
+    }
+}
+
+/// Return where a token can start relative to the end of a previous token with the given direction:
+/// the minimum start and the forbidden grid spaces that represent the "continuation" of the
+/// previous token.
+fn next_start_offsets(direction: Direction) -> (XY, Vec<XY>) {
+    match direction {
+        Direction::Point => (XY::new(2, 0), vec![XY::new(0, 1), XY::new(1, 1)]),
+        Direction::Horizontal => (XY::new(2, 0), vec![]),
+        Direction::Vertical => (XY::new(1, 0), vec![XY::new(0, 1)]),
+        Direction::Diagonal => (XY::new(1, 0), vec![XY::new(1, 1)]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::phrase_book::PhraseBook;
+    use crate::tokenize::{tokenize, MergeObjective};
+
+    fn restriction_after_half(reading_axis: ReadingAxis) -> PositionRestriction {
+        let phrase_book = PhraseBook::from_phrases(&["HALF PAST"]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+        let tokens = phrase_book
+            .phrases()
+            .iter()
+            .flat_map(|phrase| &phrase.words)
+            .map(|&word| token_graph.group(word).1)
+            .collect::<Vec<_>>();
+
+        let half = OrientedToken::orientations(tokens[0], false)[0];
+        let past = OrientedToken::orientations(tokens[1], false)[0];
+        assert_eq!(half.direction(), Direction::Horizontal);
+
+        let positioned_half = PositionedToken::new(half, XY::ORIGIN);
+        PositionRestriction::new(&relations, &[positioned_half], past, reading_axis)
+    }
+
+    /// Return whether "EIGHT" can start at each position, after "ONE" was positioned at the origin
+    /// with the given direction
+    fn valid_starts_after_one(
+        direction: Direction,
+        separate_shared_boundaries: bool,
+        starts: &[XY],
+    ) -> Vec<bool> {
+        let phrase_book = PhraseBook::from_phrases(&["ONE EIGHT"]);
+        let token_graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let relations = TokenRelations::new(&token_graph, phrase_book.phrases());
+        let words = &phrase_book.phrases()[0].words;
+        let one = token_graph.group(words[0]).1;
+        let eight = token_graph.group(words[1]).1;
+
+        let oriented_one = OrientedToken::orientations(one, false)
+            .into_iter()
+            .find(|oriented| oriented.direction() == direction)
+            .unwrap();
+        let oriented_eight = OrientedToken::orientations(eight, false)[0];
+        let positioned_one = PositionedToken::new(oriented_one, XY::ORIGIN);
+
+        let mut restriction = PositionRestriction::new(
+            &relations,
+            &[positioned_one],
+            oriented_eight,
+            ReadingAxis::RowMajor,
+        );
+        if separate_shared_boundaries {
+            let one_letters = positioned_one.iter(one).collect::<Vec<_>>();
+            restriction.separate_shared_boundaries(
+                &relations,
+                &[positioned_one],
+                eight,
+                oriented_eight,
+                |pos| {
+                    one_letters
+                        .iter()
+                        .find(|&&(one_pos, _)| one_pos == pos)
+                        .map(|&(_, letter)| letter)
+                },
+            );
+        }
+
+        starts
+            .iter()
+            .map(|&start| restriction.is_valid_start(start))
+            .collect()
+    }
+
+    #[test]
+    fn shared_boundary_horizontal() {
+        // "ONE" ends at (2, 0), so "EIGHT" could start just below its "E"
+        let starts = [XY::new(1, 1), XY::new(2, 1), XY::new(3, 1), XY::new(4, 1)];
+        assert_eq!(
+            valid_starts_after_one(Direction::Horizontal, false, &starts),
+            [true, true, true, true]
+        );
+        assert_eq!(
+            valid_starts_after_one(Direction::Horizontal, true, &starts),
+            [false, false, false, true]
+        );
+    }
+
+    #[test]
+    fn shared_boundary_vertical() {
+        // "ONE" ends at (0, 2), so "EIGHT" could start just right of its "E"
+        let starts = [XY::new(1, 2), XY::new(2, 2), XY::new(1, 3)];
+        assert_eq!(
+            valid_starts_after_one(Direction::Vertical, false, &starts),
+            [true, true, true]
+        );
+        assert_eq!(
+            valid_starts_after_one(Direction::Vertical, true, &starts),
+            [false, true, false]
+        );
+    }
+
+    #[test]
+    fn row_major() {
+        let restriction = restriction_after_half(ReadingAxis::RowMajor);
+
+        assert!(!restriction.is_valid_start(XY::new(4, 0)));
+        assert!(restriction.is_valid_start(XY::new(5, 0)));
+        assert!(restriction.is_valid_start(XY::new(0, 1)));
+    }
+
+    #[test]
+    fn column_major() {
+        let restriction = restriction_after_half(ReadingAxis::ColumnMajor);
+
+        assert!(!restriction.is_valid_start(XY::new(4, 0)));
+        assert!(restriction.is_valid_start(XY::new(5, 0)));
+        assert!(!restriction.is_valid_start(XY::new(0, 1)));
+        assert!(restriction.is_valid_start(XY::new(3, 1)));
     }
 }
@@ -1,10 +1,10 @@
 use crate::models::letter::Letter;
+use crate::models::token::Token;
 use crate::models::token::TokenId;
-use crate::Token;
 use std::ops::{Add, AddAssign, Mul, Sub};
 
 /// Represent a token with a given [`Direction`]
-#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct OrientedToken {
     token: TokenId,
     direction: Direction,
@@ -12,7 +12,7 @@ pub struct OrientedToken {
 }
 
 /// Represent a token positioned in a grid
-#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct PositionedToken {
     start: XY,
     oriented: OrientedToken,
@@ -26,7 +26,7 @@ pub struct XY {
 }
 
 /// Represent a possible orientation
-#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
 #[repr(u8)]
 pub enum Direction {
     /// A token with a single letter has no determined direction
@@ -38,24 +38,20 @@ pub enum Direction {
 
 impl OrientedToken {
     pub fn orientations(token: &Token, allow_diagonal: bool) -> Vec<Self> {
-        let size = token.text.letters().len() as i16;
+        let size = token.letters_len() as i16;
         let with_direction = |direction| OrientedToken {
             token: token.id,
             direction,
             size,
         };
 
-        match (size, allow_diagonal) {
-            (1, _) => vec![with_direction(Direction::Point)],
-            (_, false) => vec![
-                with_direction(Direction::Horizontal),
-                with_direction(Direction::Vertical),
-            ],
-            (_, true) => vec![
-                with_direction(Direction::Horizontal),
-                with_direction(Direction::Vertical),
-                with_direction(Direction::Diagonal),
-            ],
+        if size == 1 {
+            vec![with_direction(Direction::Point)]
+        } else {
+            Direction::all(allow_diagonal)
+                .into_iter()
+                .map(with_direction)
+                .collect()
         }
     }
 
@@ -81,6 +77,15 @@ impl PositionedToken {
         self.oriented.token_id()
     }
 
+    pub fn start(self) -> XY {
+        self.start
+    }
+
+    /// Return the same token, translated by `offset`
+    pub fn shifted(self, offset: XY) -> Self {
+        PositionedToken::new(self.oriented, self.start + offset)
+    }
+
     pub fn end(self) -> XY {
         self.start + self.direction().as_xy() * (self.size() - 1)
     }
@@ -97,7 +102,7 @@ impl PositionedToken {
     pub fn iter(self, token: &Token) -> impl Iterator<Item = (XY, Letter)> + '_ {
         assert_eq!(self.token_id(), token.id);
         token
-            .text
+            .text()
             .letters()
             .iter()
             .enumerate()
@@ -122,6 +127,23 @@ impl XY {
 }
 
 impl Direction {
+    /// Return the directions a token with more than one letter can take
+    pub fn all(allow_diagonal: bool) -> Vec<Direction> {
+        if allow_diagonal {
+            vec![
+                Direction::Horizontal,
+                Direction::Vertical,
+                Direction::Diagonal,
+            ]
+        } else {
+            vec![Direction::Horizontal, Direction::Vertical]
+        }
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        self == Direction::Diagonal
+    }
+
     pub fn as_xy(self) -> XY {
         match self {
             Direction::Horizontal => XY::new(1, 0),
@@ -132,11 +154,16 @@ impl Direction {
     }
 }
 
+// The arithmetic below is checked even in release builds, since a silent wrap around would corrupt
+// the grid
 impl Add for XY {
     type Output = XY;
 
     fn add(self, rhs: Self) -> Self::Output {
-        XY::new(self.x + rhs.x, self.y + rhs.y)
+        XY::new(
+            self.x.checked_add(rhs.x).expect("XY overflow"),
+            self.y.checked_add(rhs.y).expect("XY overflow"),
+        )
     }
 }
 
@@ -144,14 +171,16 @@ impl Sub for XY {
     type Output = XY;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        XY::new(self.x - rhs.x, self.y - rhs.y)
+        XY::new(
+            self.x.checked_sub(rhs.x).expect("XY overflow"),
+            self.y.checked_sub(rhs.y).expect("XY overflow"),
+        )
     }
 }
 
 impl AddAssign for XY {
     fn add_assign(&mut self, rhs: Self) {
-        self.x += rhs.x;
-        self.y += rhs.y;
+        *self = *self + rhs;
     }
 }
 
@@ -159,7 +188,10 @@ impl Mul<i16> for XY {
     type Output = XY;
 
     fn mul(self, rhs: i16) -> Self::Output {
-        XY::new(self.x * rhs, self.y * rhs)
+        XY::new(
+            self.x.checked_mul(rhs).expect("XY overflow"),
+            self.y.checked_mul(rhs).expect("XY overflow"),
+        )
     }
 }
 
@@ -184,3 +216,29 @@ impl Sub<XY> for PositionedToken {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directions() {
+        assert_eq!(
+            Direction::all(false),
+            vec![Direction::Horizontal, Direction::Vertical]
+        );
+        assert_eq!(
+            Direction::all(true),
+            vec![
+                Direction::Horizontal,
+                Direction::Vertical,
+                Direction::Diagonal
+            ]
+        );
+
+        assert!(!Direction::Point.is_diagonal());
+        assert!(!Direction::Horizontal.is_diagonal());
+        assert!(!Direction::Vertical.is_diagonal());
+        assert!(Direction::Diagonal.is_diagonal());
+    }
+}
@@ -1,5 +1,7 @@
+use anyhow::{anyhow, ensure, Error, Result};
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::str::FromStr;
 
 /// Represent an instant the day, from 00:00 to 23:59
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
@@ -8,6 +10,36 @@ pub struct Time {
     minutes: u8,
 }
 
+/// An inclusive range of times of the day, like from 09:00 to 17:00. It cannot wrap around
+/// midnight.
+#[derive(Debug, Copy, Clone)]
+pub struct TimeRange {
+    start: Time,
+    end: Time,
+}
+
+/// Round the minutes of a time to a multiple of `step`, like the classic word clocks, which only
+/// show every five minutes
+#[derive(Debug, Copy, Clone)]
+pub struct Rounding {
+    pub step: u8,
+    pub mode: RoundingMode,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum RoundingMode {
+    /// 09:04 is rounded to 09:00 with a step of 5 minutes
+    Down,
+    /// 09:02 is rounded to 09:00 and 09:03 to 09:05 with a step of 5 minutes
+    Nearest,
+}
+
+/// A signed amount of time, like "+05:30", to shift times by
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct TimeOffset {
+    minutes: i16,
+}
+
 impl Time {
     pub fn new(hours: u8, minutes: u8) -> Self {
         assert!(hours < 24);
@@ -15,6 +47,13 @@ impl Time {
         Time { hours, minutes }
     }
 
+    /// Create a new time, returning an error if it is not valid
+    pub fn at(hours: u8, minutes: u8) -> Result<Self> {
+        ensure!(hours < 24, "Invalid hours: {}", hours);
+        ensure!(minutes < 60, "Invalid minutes: {}", minutes);
+        Ok(Time { hours, minutes })
+    }
+
     pub fn hours(self) -> u8 {
         self.hours
     }
@@ -23,9 +62,147 @@ impl Time {
         self.minutes
     }
 
+    /// Whether the minutes are one of the "special" ones that are usually spelled with dedicated
+    /// words: o'clock, quarter past, half past or quarter to
+    pub fn is_special(self) -> bool {
+        self.is_o_clock() || self.is_quarter_past() || self.is_half_past() || self.is_quarter_to()
+    }
+
+    pub fn is_o_clock(self) -> bool {
+        self.minutes == 0
+    }
+
+    pub fn is_quarter_past(self) -> bool {
+        self.minutes == 15
+    }
+
+    pub fn is_half_past(self) -> bool {
+        self.minutes == 30
+    }
+
+    pub fn is_quarter_to(self) -> bool {
+        self.minutes == 45
+    }
+
     pub fn all_times() -> impl Iterator<Item = Time> {
         (0..24).flat_map(|hours| (0..60).map(move |minutes| Time::new(hours, minutes)))
     }
+
+    /// Iterate from `from` (inclusive) to `to` (exclusive), advancing `step` minutes each time.
+    /// If `to` is not after `from`, the iterator is empty.
+    ///
+    /// # Panics
+    /// It will panic if `step` is zero
+    pub fn range(from: Time, to: Time, step: u16) -> impl Iterator<Item = Time> {
+        assert!(step > 0);
+        (from.minutes_of_day()..to.minutes_of_day())
+            .step_by(step as usize)
+            .map(|minutes| Time::new((minutes / 60) as u8, (minutes % 60) as u8))
+    }
+
+    /// Return the time after this one by the given offset, wrapping around midnight
+    pub fn shifted(self, offset: TimeOffset) -> Time {
+        let minutes = (self.minutes_of_day() as i16 + offset.minutes).rem_euclid(24 * 60);
+        Time::new((minutes / 60) as u8, (minutes % 60) as u8)
+    }
+
+    /// Return this time with its minutes rounded to a multiple of the step, wrapping around
+    /// midnight when rounding to nearest
+    ///
+    /// # Panics
+    /// It will panic if the step is zero
+    pub fn rounded(self, rounding: Rounding) -> Time {
+        assert!(rounding.step > 0);
+        let minutes = self.minutes_of_day();
+        let down = minutes - self.minutes as u16 % rounding.step as u16;
+        // The next multiple is capped at the next hour, when the step does not divide 60
+        let up = (down + rounding.step as u16).min(minutes - self.minutes as u16 + 60);
+        let minutes = match rounding.mode {
+            RoundingMode::Nearest if up - minutes <= minutes - down => up % (24 * 60),
+            _ => down,
+        };
+        Time::new((minutes / 60) as u8, (minutes % 60) as u8)
+    }
+
+    fn minutes_of_day(self) -> u16 {
+        self.hours as u16 * 60 + self.minutes as u16
+    }
+}
+
+impl TimeRange {
+    /// Create a new range, returning an error if `end` is before `start`
+    pub fn new(start: Time, end: Time) -> Result<Self> {
+        ensure!(
+            start.minutes_of_day() <= end.minutes_of_day(),
+            "The time range ends at {}, before it starts at {}. Ranges that wrap around midnight \
+            are not supported",
+            end,
+            start
+        );
+        Ok(TimeRange { start, end })
+    }
+
+    pub fn contains(self, time: Time) -> bool {
+        (self.start.minutes_of_day()..=self.end.minutes_of_day()).contains(&time.minutes_of_day())
+    }
+}
+
+impl Default for TimeRange {
+    /// The whole day, from 00:00 to 23:59
+    fn default() -> Self {
+        TimeRange {
+            start: Time::new(0, 0),
+            end: Time::new(23, 59),
+        }
+    }
+}
+
+impl FromStr for Time {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let error = || anyhow!("Time was not recognized: {}", s);
+        let (hours, minutes) = s.split_once(':').ok_or_else(error)?;
+        let hours = hours.parse().map_err(|_| error())?;
+        let minutes = minutes.parse().map_err(|_| error())?;
+        Time::at(hours, minutes)
+    }
+}
+
+impl FromStr for RoundingMode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "down" => Ok(RoundingMode::Down),
+            "nearest" => Ok(RoundingMode::Nearest),
+            _ => Err(anyhow!("Rounding mode was not recognized: {}", s)),
+        }
+    }
+}
+
+impl FromStr for TimeOffset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let error = || anyhow!("Time offset was not recognized: {}", s);
+        let (sign, rest) = match s.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (hours, minutes) = rest.split_once(':').ok_or_else(error)?;
+        let hours: i16 = hours.parse().map_err(|_| error())?;
+        let minutes: i16 = minutes.parse().map_err(|_| error())?;
+        ensure!(
+            (0..24).contains(&hours) && (0..60).contains(&minutes),
+            "Time offset is out of range: {}",
+            s
+        );
+
+        Ok(TimeOffset {
+            minutes: sign * (hours * 60 + minutes),
+        })
+    }
 }
 
 impl fmt::Display for Time {
@@ -33,3 +210,117 @@ impl fmt::Display for Time {
         write!(f, "{:02}:{:02}", self.hours, self.minutes)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use itertools::Itertools;
+
+    fn format(times: impl Iterator<Item = Time>) -> Vec<String> {
+        times.map(|time| time.to_string()).collect()
+    }
+
+    #[test]
+    fn is_special() {
+        let special = Time::range(Time::new(7, 0), Time::new(8, 0), 1)
+            .filter(|time| time.is_special())
+            .collect_vec();
+        assert_eq!(
+            format(special.iter().copied()),
+            ["07:00", "07:15", "07:30", "07:45"]
+        );
+
+        assert!(special[0].is_o_clock());
+        assert!(special[1].is_quarter_past());
+        assert!(special[2].is_half_past());
+        assert!(special[3].is_quarter_to());
+        let kinds = |time: Time| {
+            [
+                time.is_o_clock(),
+                time.is_quarter_past(),
+                time.is_half_past(),
+                time.is_quarter_to(),
+            ]
+        };
+        for time in Time::range(Time::new(7, 0), Time::new(8, 0), 1) {
+            let num_kinds = kinds(time).iter().filter(|&&kind| kind).count();
+            assert_eq!(num_kinds, time.is_special() as usize);
+        }
+    }
+
+    #[test]
+    fn shifted() {
+        let offset: TimeOffset = "+05:30".parse().unwrap();
+        assert_eq!(Time::new(10, 0).shifted(offset).to_string(), "15:30");
+        assert_eq!(Time::new(20, 45).shifted(offset).to_string(), "02:15");
+
+        let offset: TimeOffset = "-01:15".parse().unwrap();
+        assert_eq!(Time::new(0, 10).shifted(offset).to_string(), "22:55");
+
+        assert!("05:60".parse::<TimeOffset>().is_err());
+        assert!("+5".parse::<TimeOffset>().is_err());
+    }
+
+    #[test]
+    fn at() {
+        assert_eq!(Time::at(23, 59).unwrap().to_string(), "23:59");
+        assert!(Time::at(24, 0).is_err());
+        assert!(Time::at(0, 60).is_err());
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("09:05".parse::<Time>().unwrap().to_string(), "09:05");
+        assert_eq!("9:05".parse::<Time>().unwrap().to_string(), "09:05");
+        assert!("24:00".parse::<Time>().is_err());
+        assert!("0905".parse::<Time>().is_err());
+    }
+
+    #[test]
+    fn time_range() {
+        let range = TimeRange::new(Time::new(9, 0), Time::new(17, 0)).unwrap();
+        assert!(!range.contains(Time::new(8, 59)));
+        assert!(range.contains(Time::new(9, 0)));
+        assert!(range.contains(Time::new(17, 0)));
+        assert!(!range.contains(Time::new(17, 1)));
+
+        assert!(TimeRange::new(Time::new(17, 0), Time::new(9, 0)).is_err());
+        assert_eq!(
+            Time::all_times()
+                .filter(|&time| TimeRange::default().contains(time))
+                .count(),
+            24 * 60
+        );
+    }
+
+    #[test]
+    fn rounded() {
+        let round = |hours, minutes, step, mode| {
+            Time::new(hours, minutes)
+                .rounded(Rounding { step, mode })
+                .to_string()
+        };
+
+        assert_eq!(round(9, 4, 5, RoundingMode::Down), "09:00");
+        assert_eq!(round(9, 5, 5, RoundingMode::Down), "09:05");
+        assert_eq!(round(9, 2, 5, RoundingMode::Nearest), "09:00");
+        assert_eq!(round(9, 3, 5, RoundingMode::Nearest), "09:05");
+        assert_eq!(round(9, 58, 5, RoundingMode::Nearest), "10:00");
+        assert_eq!(round(23, 58, 5, RoundingMode::Nearest), "00:00");
+        assert_eq!(round(9, 59, 7, RoundingMode::Down), "09:56");
+        assert_eq!(round(9, 59, 7, RoundingMode::Nearest), "10:00");
+    }
+
+    #[test]
+    fn range() {
+        let from = Time::new(11, 50);
+        let to = Time::new(12, 10);
+
+        assert_eq!(
+            format(Time::range(from, to, 7)),
+            vec!["11:50", "11:57", "12:04"]
+        );
+        assert_eq!(format(Time::range(from, from, 1)), Vec::<String>::new());
+        assert_eq!(format(Time::range(to, from, 1)), Vec::<String>::new());
+    }
+}
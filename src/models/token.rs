@@ -1,3 +1,4 @@
+use crate::models::letter::Letter;
 use crate::models::text::Text;
 use crate::models::word::{Word, WordId};
 use std::fmt;
@@ -6,11 +7,14 @@ use std::fmt;
 #[derive(Debug, Clone)]
 pub struct Token {
     pub id: TokenId,
-    pub text: Text,
+    /// Private, so that `letters_len` always matches it
+    text: Text,
     pub words: Vec<WordId>,
+    /// Cached length of `text`, since it is used in hot sorting paths
+    letters_len: usize,
 }
 
-#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct TokenId(pub u16);
 
 impl Token {
@@ -19,8 +23,25 @@ impl Token {
             id: TokenId(word.id.0),
             text: word.text.clone(),
             words: vec![word.id],
+            letters_len: word.text.letters().len(),
         }
     }
+
+    pub fn text(&self) -> &Text {
+        &self.text
+    }
+
+    pub fn letters_len(&self) -> usize {
+        self.letters_len
+    }
+
+    pub fn first_letter(&self) -> Letter {
+        self.text.letters()[0]
+    }
+
+    pub fn last_letter(&self) -> Letter {
+        self.text.letters()[self.letters_len - 1]
+    }
 }
 
 impl fmt::Display for Token {
@@ -28,3 +49,23 @@ impl fmt::Display for Token {
         write!(f, "{}", self.text)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::word::WordId;
+
+    #[test]
+    fn letters_len() {
+        let word = |id, text: &str| Word {
+            id: WordId(id),
+            text: text.parse().unwrap(),
+        };
+        let mut token = Token::new(&word(0, "QUARTER"));
+        token.words.extend(Token::new(&word(1, "QUARTER")).words);
+
+        assert_eq!(token.letters_len(), token.text.letters().len());
+        assert_eq!(token.first_letter(), Letter::Q);
+        assert_eq!(token.last_letter(), Letter::R);
+    }
+}
@@ -1,13 +1,19 @@
 use crate::models::merge_dag::MergeDag;
+use crate::models::phrase::Phrase;
 use crate::models::token::{Token, TokenId};
 use crate::models::word::WordId;
-use crate::Phrase;
 use itertools::Itertools;
 
 /// Represents the relative positioning constraint between any pair of tokens
 #[derive(Debug, Clone)]
 pub struct TokenRelations {
     relations: Vec<Vec<TokenRelation>>,
+    /// The number of phrases in which each pair of tokens appear together
+    co_occurrences: Vec<(TokenId, TokenId, u16)>,
+    /// The number of times each token is directly followed by another one in the phrases
+    successions: Vec<(TokenId, TokenId, u16)>,
+    /// Whether each pair of tokens appear together in some phrase
+    coexistence: Vec<Vec<bool>>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -36,7 +42,49 @@ impl TokenRelations {
             }
         }
 
-        TokenRelations { relations }
+        let co_occurrences: Vec<_> = phrases
+            .iter()
+            .flat_map(|phrase| {
+                let tokens = phrase
+                    .words
+                    .iter()
+                    .map(|&word| graph.group(word).1.id)
+                    .sorted()
+                    .dedup()
+                    .collect_vec();
+                tokens.into_iter().tuple_combinations::<(_, _)>()
+            })
+            .sorted()
+            .dedup_with_count()
+            .map(|(count, (a, b))| (a, b, count as u16))
+            .collect();
+
+        let successions = phrases
+            .iter()
+            .flat_map(|phrase| {
+                phrase
+                    .words
+                    .iter()
+                    .map(|&word| graph.group(word).1.id)
+                    .tuple_windows::<(_, _)>()
+            })
+            .sorted()
+            .dedup_with_count()
+            .map(|(count, (before, after))| (before, after, count as u16))
+            .collect();
+
+        let mut coexistence = vec![vec![false; length]; length];
+        for &(a, b, _) in &co_occurrences {
+            coexistence[a.0 as usize][b.0 as usize] = true;
+            coexistence[b.0 as usize][a.0 as usize] = true;
+        }
+
+        TokenRelations {
+            relations,
+            co_occurrences,
+            successions,
+            coexistence,
+        }
     }
 
     /// Return the relation between these two tokens. For example, [`TokenRelation::IsBefore`] is
@@ -44,4 +92,74 @@ impl TokenRelations {
     pub fn get(&self, a: TokenId, b: TokenId) -> TokenRelation {
         self.relations[a.0 as usize][b.0 as usize]
     }
+
+    /// Return whether `a` must be positioned before `b`
+    pub fn is_before(&self, a: TokenId, b: TokenId) -> bool {
+        matches!(self.get(a, b), TokenRelation::IsBefore)
+    }
+
+    /// Return whether `a` must be positioned after `b`
+    pub fn is_after(&self, a: TokenId, b: TokenId) -> bool {
+        matches!(self.get(a, b), TokenRelation::IsAfter)
+    }
+
+    /// Return whether the two tokens appear together in some phrase, even if not next to each
+    /// other
+    pub fn coexist(&self, a: TokenId, b: TokenId) -> bool {
+        self.coexistence[a.0 as usize][b.0 as usize]
+    }
+
+    /// Return how many tokens must be positioned before or after this one
+    pub fn num_constraints(&self, token: TokenId) -> usize {
+        self.relations[token.0 as usize]
+            .iter()
+            .filter(|relation| !matches!(relation, TokenRelation::None))
+            .count()
+    }
+
+    /// Return each pair of tokens that appear together in some phrase, with the number of such
+    /// phrases
+    pub fn co_occurrences(&self) -> &[(TokenId, TokenId, u16)] {
+        &self.co_occurrences
+    }
+
+    /// Return each pair of tokens in which the first one is directly followed by the second one in
+    /// some phrase, with the number of times it happens
+    pub fn successions(&self) -> &[(TokenId, TokenId, u16)] {
+        &self.successions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::phrase_book::PhraseBook;
+    use crate::tokenize::{tokenize, MergeObjective};
+
+    #[test]
+    fn relations() {
+        let phrase_book = PhraseBook::from_phrases(&["IT IS ONE", "IT IS TWO", "HALF PAST ONE"]);
+        let graph = tokenize(&phrase_book, 1, MergeObjective::FewestTokens);
+        let relations = TokenRelations::new(&graph, phrase_book.phrases());
+        let token = |text: &str| {
+            graph
+                .groups()
+                .find(|(_, token)| token.text().to_string() == text)
+                .unwrap()
+                .1
+                .id
+        };
+
+        assert!(relations.is_before(token("IT"), token("IS")));
+        assert!(relations.is_after(token("IS"), token("IT")));
+        assert!(!relations.is_before(token("IT"), token("ONE")));
+        assert!(!relations.is_after(token("IT"), token("ONE")));
+
+        // "IT" and "ONE" are not adjacent, but are in the same phrase
+        assert!(relations.coexist(token("IT"), token("ONE")));
+        assert!(relations.coexist(token("ONE"), token("IT")));
+        assert!(relations.coexist(token("HALF"), token("ONE")));
+        assert!(!relations.coexist(token("IT"), token("HALF")));
+        assert!(!relations.coexist(token("ONE"), token("TWO")));
+    }
 }
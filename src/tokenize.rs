@@ -1,13 +1,18 @@
 // pub mod token_graph;
 
+use crate::models::explanation::{ExplainedMerge, ExplainedSequence, Explanation, MergeDecision};
 use crate::models::merge_dag::MergeDag;
+use crate::models::phrase::Phrase;
 use crate::models::phrase_book::PhraseBook;
 use crate::models::text::Text;
 use crate::models::token::Token;
 use crate::models::word::WordId;
+use anyhow::{anyhow, Error};
 use itertools::Itertools;
 use std::cmp::Reverse;
 use std::collections::{BTreeMap, BTreeSet};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
 
 #[derive(Debug)]
 pub struct RepeatedSequence<'a> {
@@ -15,7 +20,67 @@ pub struct RepeatedSequence<'a> {
     instances: Vec<&'a [WordId]>,
 }
 
-pub fn tokenize(book: &PhraseBook, chain_growth_head_space: i32) -> MergeDag<WordId, Token> {
+/// Determine which merges of words into tokens are accepted, since fewer tokens usually require
+/// longer chains of tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MergeObjective {
+    /// Accept merges that grow chains, up to the maximum chain size
+    FewestTokens,
+    /// Reject any merge that grows a chain
+    ShortestChains,
+    /// Accept merges that grow a chain by a single token, up to the maximum chain size
+    Balanced,
+}
+
+impl MergeObjective {
+    fn accepts(self, old_chain_size: i32, new_chain_size: i32, max_chain_size: i32) -> bool {
+        let growth = new_chain_size - old_chain_size;
+        let within_max = new_chain_size <= max_chain_size;
+        match self {
+            MergeObjective::FewestTokens => growth <= 0 || within_max,
+            MergeObjective::ShortestChains => growth <= 0,
+            MergeObjective::Balanced => growth <= 0 || (growth == 1 && within_max),
+        }
+    }
+}
+
+impl FromStr for MergeObjective {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fewest-tokens" => Ok(MergeObjective::FewestTokens),
+            "shortest-chains" => Ok(MergeObjective::ShortestChains),
+            "balanced" => Ok(MergeObjective::Balanced),
+            _ => Err(anyhow!("MergeObjective was not recognized: {}", s)),
+        }
+    }
+}
+
+pub fn tokenize(
+    book: &PhraseBook,
+    chain_growth_head_space: i32,
+    objective: MergeObjective,
+) -> MergeDag<WordId, Token> {
+    tokenize_with(book, chain_growth_head_space, objective, None)
+}
+
+/// Like [`tokenize()`], but also record the repeated sequences and each merge decision
+pub fn tokenize_explained(
+    book: &PhraseBook,
+    chain_growth_head_space: i32,
+    objective: MergeObjective,
+    explanation: &mut Explanation,
+) -> MergeDag<WordId, Token> {
+    tokenize_with(book, chain_growth_head_space, objective, Some(explanation))
+}
+
+fn tokenize_with(
+    book: &PhraseBook,
+    chain_growth_head_space: i32,
+    objective: MergeObjective,
+    mut explanation: Option<&mut Explanation>,
+) -> MergeDag<WordId, Token> {
     let mut seed_tokens = vec![];
     let mut edges = vec![];
     let mut longest_phrase = 0;
@@ -44,8 +109,23 @@ pub fn tokenize(book: &PhraseBook, chain_growth_head_space: i32) -> MergeDag<Wor
 
     let sequences = extract_sequences(book);
     log::info!("Will try to merge {} sequences", sequences.len());
+    if let Some(explanation) = &mut explanation {
+        explanation.sequences = sequences
+            .iter()
+            .map(|sequence| ExplainedSequence {
+                texts: sequence.texts.iter().map(|text| text.to_string()).collect(),
+                instances: sequence.instances.len(),
+            })
+            .collect();
+    }
     for sequence in &sequences {
-        merge_sequence(&mut graph, sequence, max_chain_size);
+        merge_sequence(
+            &mut graph,
+            sequence,
+            max_chain_size,
+            objective,
+            explanation.as_deref_mut(),
+        );
     }
 
     if log::log_enabled!(log::Level::Debug) {
@@ -68,10 +148,61 @@ pub fn tokenize(book: &PhraseBook, chain_growth_head_space: i32) -> MergeDag<Wor
     graph
 }
 
+/// Pick the value of `chain_growth_head_space` in `candidates` that minimizes the length of the
+/// longest path in the token graph times the number of tokens. This is a cheap proxy for the size of
+/// the final grid. Ties are won by the smallest value.
+pub fn auto_chain_growth_head_space(
+    book: &PhraseBook,
+    candidates: RangeInclusive<i32>,
+    objective: MergeObjective,
+) -> i32 {
+    candidates
+        .map(|head_space| {
+            let graph = tokenize(book, head_space, objective);
+            let metric = graph.longest_path().len() * graph.groups_len();
+            log::info!(
+                "With chain_growth_head_space = {}, the metric is {}",
+                head_space,
+                metric
+            );
+            (metric, head_space)
+        })
+        .min()
+        .expect("There must be at least one candidate")
+        .1
+}
+
+/// Represent the token graph as two CSV tables: the tokens, with their text and number of letters,
+/// and the ordering edges between them. The ids tell apart the tokens with the same text.
+pub fn tokens_csv(graph: &MergeDag<WordId, Token>) -> (String, String) {
+    let mut nodes = String::from("id,text,letters\n");
+    for (_, token) in graph.groups() {
+        nodes += &format!("{},{},{}\n", token.id.0, token.text(), token.letters_len());
+    }
+
+    let mut edges = String::from("from_id,from_text,to_id,to_text\n");
+    for (from, to) in graph.edges() {
+        let from = &graph[from];
+        let to = &graph[to];
+        edges += &format!("{},{},{},{}\n", from.id.0, from.text(), to.id.0, to.text());
+    }
+
+    (nodes, edges)
+}
+
+/// Return the token of each word of the phrase, in order
+pub fn phrase_tokens<'a>(graph: &'a MergeDag<WordId, Token>, phrase: &Phrase) -> Vec<&'a Token> {
+    phrase
+        .words
+        .iter()
+        .map(|&word| graph.group(word).1)
+        .collect()
+}
+
 /// Extract all sequences of one or more words that repeat at least twice in the phrases.
 /// The sequences are sorted by descending word length first and then total number of letters in all
 /// instances.
-fn extract_sequences(book: &PhraseBook) -> Vec<RepeatedSequence> {
+fn extract_sequences(book: &PhraseBook) -> Vec<RepeatedSequence<'_>> {
     let max_words_per_phrase = book
         .phrases()
         .iter()
@@ -93,7 +224,7 @@ fn extract_sequences(book: &PhraseBook) -> Vec<RepeatedSequence> {
 /// Extract sequences of `length` words from all phrases and collect all those that repeat more than
 /// once. For each sequence, it will regroup all the word locations that compose each instance of
 /// the repeated sequence.
-fn extract_sequences_with_length(book: &PhraseBook, length: usize) -> Vec<RepeatedSequence> {
+fn extract_sequences_with_length(book: &PhraseBook, length: usize) -> Vec<RepeatedSequence<'_>> {
     // Collect all sequences
     let mut sequences: BTreeMap<_, Vec<_>> = BTreeMap::new();
     for phrase in book.phrases() {
@@ -123,12 +254,20 @@ fn merge_sequence(
     graph: &mut MergeDag<WordId, Token>,
     sequence: &RepeatedSequence,
     max_chain_size: i32,
+    objective: MergeObjective,
+    mut explanation: Option<&mut Explanation>,
 ) {
     log::debug!("Will merge sequence: {}", sequence.texts.iter().format(" "));
 
     for i in 0..sequence.texts.len() {
         let locations = sequence.instances.iter().map(|loc| loc[i]).collect_vec();
-        merge_locations(graph, &locations, max_chain_size);
+        merge_locations(
+            graph,
+            &locations,
+            max_chain_size,
+            objective,
+            explanation.as_deref_mut(),
+        );
     }
 }
 
@@ -137,7 +276,13 @@ fn merge_sequence(
 /// its own. The following locations will try to merge with the first group. When not possible, it
 /// will try with the second, and so on until no group accepts it. In this case, a new group will
 /// created again.
-fn merge_locations(graph: &mut MergeDag<WordId, Token>, words: &[WordId], max_chain_size: i32) {
+fn merge_locations(
+    graph: &mut MergeDag<WordId, Token>,
+    words: &[WordId],
+    max_chain_size: i32,
+    objective: MergeObjective,
+    mut explanation: Option<&mut Explanation>,
+) {
     let mut group_roots = Vec::new();
 
     let unique_tokens_before: BTreeSet<_> = words
@@ -159,13 +304,31 @@ fn merge_locations(graph: &mut MergeDag<WordId, Token>, words: &[WordId], max_ch
             let old_chain_size = word_chain.size().max(root_chain.size());
             let new_chain_size = root_chain.merged_with(word_chain).size();
 
-            let grows_chain = new_chain_size > old_chain_size;
-            let new_chain_accepted = new_chain_size <= max_chain_size;
+            let decision = if !objective.accepts(old_chain_size, new_chain_size, max_chain_size) {
+                MergeDecision::ChainTooLong
+            } else if graph.has_path(root, word_group) {
+                MergeDecision::Cycle
+            } else {
+                let merged_groups =
+                    graph.merge_groups(root, word_group, |base_token, new_token| {
+                        let mut token = base_token.clone();
+                        token.words.extend(&new_token.words);
+                        Some(token)
+                    });
+                assert!(merged_groups, "merging tokens is never rejected");
+                MergeDecision::Accepted
+            };
 
-            if (!grows_chain || new_chain_accepted) && !graph.has_path(root, word_group) {
-                graph.merge_groups(root, word_group, |base_token, new_token| {
-                    base_token.words.extend(new_token.words);
+            if let Some(explanation) = &mut explanation {
+                explanation.merges.push(ExplainedMerge {
+                    text: graph.group(word).1.to_string(),
+                    old_chain_size,
+                    new_chain_size,
+                    max_chain_size,
+                    decision,
                 });
+            }
+            if decision == MergeDecision::Accepted {
                 merged = true;
                 break;
             }
@@ -188,3 +351,131 @@ fn merge_locations(graph: &mut MergeDag<WordId, Token>, words: &[WordId], max_ch
         unique_tokens_after.len(),
     );
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::io::GridInput;
+
+    fn groups_len(
+        phrases: &[&str],
+        chain_growth_head_space: i32,
+        objective: MergeObjective,
+    ) -> usize {
+        tokenize(
+            &PhraseBook::from_phrases(phrases),
+            chain_growth_head_space,
+            objective,
+        )
+        .groups_len()
+    }
+
+    #[test]
+    fn phrase_tokens() {
+        let mut book = PhraseBook::default();
+        for phrase in GridInput::example().phrases {
+            book.insert_phrase(phrase.texts);
+        }
+        let graph = tokenize(&book, 1, MergeObjective::FewestTokens);
+
+        let spell = |phrase: &Phrase| {
+            super::phrase_tokens(&graph, phrase)
+                .into_iter()
+                .map(|token| token.text().to_string())
+                .join(" ")
+        };
+        assert_eq!(spell(&book.phrases()[0]), "IT IS ONE");
+        assert_eq!(spell(&book.phrases()[1]), "IT IS TWO");
+    }
+
+    #[test]
+    fn auto_chain_growth() {
+        let auto = |phrases: &[&str]| {
+            auto_chain_growth_head_space(
+                &PhraseBook::from_phrases(phrases),
+                0..=3,
+                MergeObjective::FewestTokens,
+            )
+        };
+
+        // Merging "IS" saves one token but makes the longest path longer
+        assert_eq!(auto(&["IT IS", "IS ONE"]), 0);
+
+        // Merging "IS ONE TWO" saves three tokens for one more word in the longest path
+        assert_eq!(auto(&["IT IS ONE TWO", "IS ONE TWO NOW"]), 1);
+    }
+
+    #[test]
+    fn explained() {
+        let explain = |objective| {
+            let mut explanation = Explanation::default();
+            tokenize_explained(
+                &PhraseBook::from_phrases(&["IT IS", "IS ONE"]),
+                1,
+                objective,
+                &mut explanation,
+            );
+            explanation
+        };
+
+        let explanation = explain(MergeObjective::FewestTokens);
+        let sequences = explanation
+            .sequences
+            .iter()
+            .map(|sequence| sequence.texts.join(" "))
+            .collect_vec();
+        assert_eq!(sequences, ["IS"]);
+        let merges = explanation
+            .merges
+            .iter()
+            .map(|merge| (merge.text.as_str(), merge.decision))
+            .collect_vec();
+        assert_eq!(merges, [("IS", MergeDecision::Accepted)]);
+        let json = serde_json::to_string(&explanation).unwrap();
+        assert!(json.contains(r#""text":"IS""#));
+        assert!(json.contains(r#""decision":"accepted""#));
+
+        let explanation = explain(MergeObjective::ShortestChains);
+        let merge = &explanation.merges[0];
+        assert_eq!(merge.decision, MergeDecision::ChainTooLong);
+        assert_eq!((merge.old_chain_size, merge.new_chain_size), (2, 3));
+    }
+
+    #[test]
+    fn tokens_csv() {
+        let graph = tokenize(
+            &PhraseBook::from_phrases(&["IT IS ONE", "IT IS TWO"]),
+            1,
+            MergeObjective::FewestTokens,
+        );
+        let (nodes, edges) = super::tokens_csv(&graph);
+
+        assert_eq!(nodes.lines().count(), graph.groups_len() + 1);
+        assert_eq!(edges.lines().count(), graph.edges().count() + 1);
+        let edges = edges
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let columns = line.split(',').collect_vec();
+                format!("{} {}", columns[1], columns[3])
+            })
+            .sorted()
+            .collect_vec();
+        assert_eq!(edges, ["IS ONE", "IS TWO", "IT IS"]);
+    }
+
+    #[test]
+    fn merge_objective() {
+        // Merging "IS" grows the chain by one word
+        let phrases = ["IT IS", "IS ONE"];
+        assert_eq!(groups_len(&phrases, 1, MergeObjective::FewestTokens), 3);
+        assert_eq!(groups_len(&phrases, 1, MergeObjective::Balanced), 3);
+        assert_eq!(groups_len(&phrases, 1, MergeObjective::ShortestChains), 4);
+
+        // Merging "IS" grows the chain by two words
+        let phrases = ["SO IT IS", "IS ONE NOW"];
+        assert_eq!(groups_len(&phrases, 2, MergeObjective::FewestTokens), 5);
+        assert_eq!(groups_len(&phrases, 2, MergeObjective::Balanced), 6);
+        assert_eq!(groups_len(&phrases, 2, MergeObjective::ShortestChains), 6);
+    }
+}